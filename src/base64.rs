@@ -1,106 +1,501 @@
-fn encode_byte(input: u8) -> u8 {
-    match input {
-        0 => b'A',
-        1 => b'B',
-        2 => b'C',
-        3 => b'D',
-        4 => b'E',
-        5 => b'F',
-        6 => b'G',
-        7 => b'H',
-        8 => b'I',
-        9 => b'J',
-        10 => b'K',
-        11 => b'L',
-        12 => b'M',
-        13 => b'N',
-        14 => b'O',
-        15 => b'P',
-        16 => b'Q',
-        17 => b'R',
-        18 => b'S',
-        19 => b'T',
-        20 => b'U',
-        21 => b'V',
-        22 => b'W',
-        23 => b'X',
-        24 => b'Y',
-        25 => b'Z',
-        26 => b'a',
-        27 => b'b',
-        28 => b'c',
-        29 => b'd',
-        30 => b'e',
-        31 => b'f',
-        32 => b'g',
-        33 => b'h',
-        34 => b'i',
-        35 => b'j',
-        36 => b'k',
-        37 => b'l',
-        38 => b'm',
-        39 => b'n',
-        40 => b'o',
-        41 => b'p',
-        42 => b'q',
-        43 => b'r',
-        44 => b's',
-        45 => b't',
-        46 => b'u',
-        47 => b'v',
-        48 => b'w',
-        49 => b'x',
-        50 => b'y',
-        51 => b'z',
-        52 => b'0',
-        53 => b'1',
-        54 => b'2',
-        55 => b'3',
-        56 => b'4',
-        57 => b'5',
-        58 => b'6',
-        59 => b'7',
-        60 => b'8',
-        61 => b'9',
-        62 => b'+',
-        63 => b'/',
-        _ => panic!("Invalid Base64 value: {}", input),
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// The 64 characters a variant encodes with.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Alphabet {
+    /// From Section 4 of RFC 4648, ending in `+` and `/`.
+    Standard,
+    /// The URL and filename safe alphabet from Section 5 of RFC 4648,
+    /// which ends in `-` and `_` instead.
+    UrlSafe,
+}
+
+impl Alphabet {
+    fn characters(self) -> &'static [u8; 64] {
+        match self {
+            Alphabet::Standard => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/",
+            Alphabet::UrlSafe => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+        }
+    }
+
+    fn value(self, byte: u8) -> Option<u8> {
+        match (byte, self) {
+            (b'A'..=b'Z', _) => Some(byte - b'A'),
+            (b'a'..=b'z', _) => Some(byte - b'a' + 26),
+            (b'0'..=b'9', _) => Some(byte - b'0' + 52),
+            (b'+', Alphabet::Standard) | (b'-', Alphabet::UrlSafe) => Some(62),
+            (b'/', Alphabet::Standard) | (b'_', Alphabet::UrlSafe) => Some(63),
+            _ => None,
+        }
     }
 }
 
-pub fn encode(input: &[u8]) -> Vec<u8> {
-    let mut output = Vec::new();
+/// A variant of base64: which alphabet it uses, whether the output is
+/// padded with `=` to a multiple of four characters, and whether it
+/// is broken into lines.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Engine {
+    pub alphabet: Alphabet,
+    pub padding: bool,
+    /// Characters per line of output, with lines separated by CRLF.
+    pub line_length: Option<usize>,
+}
+
+/// The base64 of Section 4 of RFC 4648.
+pub const STANDARD: Engine = Engine { alphabet: Alphabet::Standard, padding: true, line_length: None };
+
+/// The standard alphabet without padding, as Section 3.2 of RFC 4648
+/// allows when the length is known some other way.
+pub const STANDARD_NO_PAD: Engine = Engine { alphabet: Alphabet::Standard, padding: false, line_length: None };
+
+/// The alphabet that is safe in URLs and filenames, with padding.
+pub const URL_SAFE: Engine = Engine { alphabet: Alphabet::UrlSafe, padding: true, line_length: None };
+
+/// The URL-safe alphabet without padding, as used by JSON Web Tokens
+/// (Section 2 of RFC 7515) and in cookie values.
+pub const URL_SAFE_NO_PAD: Engine = Engine { alphabet: Alphabet::UrlSafe, padding: false, line_length: None };
+
+/// The Content-Transfer-Encoding for email from Section 6.8 of
+/// RFC 2045, in lines of at most 76 characters.
+pub const MIME: Engine = Engine { alphabet: Alphabet::Standard, padding: true, line_length: Some(76) };
 
-    for chunk in input.chunks(3) {
+impl Engine {
+    pub fn encode(&self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len().div_ceil(3) * 4);
+        let mut encoder = Encoder::new(*self);
+        encoder.feed(input, &mut output);
+        encoder.finish(&mut output);
+        output
+    }
+
+    /// Decodes input in this variant. In strict mode the padding must
+    /// be there or not as the variant says, and with line wrapping,
+    /// only CRLF or LF line breaks are skipped. In lenient mode either
+    /// is fine, and any whitespace is skipped.
+    pub fn decode(&self, input: &[u8], mode: Mode) -> Result<Vec<u8>, DecodeError> {
+        let mut output = Vec::with_capacity(input.len() / 4 * 3 + 2);
+        let mut decoder = Decoder::new(*self, mode);
+        decoder.feed(input, &mut output)?;
+        decoder.finish(&mut output)?;
+        Ok(output)
+    }
+}
+
+/// Encodes input a piece at a time, carrying the one or two bytes
+/// that don't yet make up a group of three over to the next call to
+/// `feed`.
+struct Encoder {
+    engine: Engine,
+    carry: [u8; 3],
+    carry_length: usize,
+    /// Characters written on the current line.
+    column: usize,
+}
+
+impl Encoder {
+    fn new(engine: Engine) -> Encoder {
+        Encoder { engine, carry: [0; 3], carry_length: 0, column: 0 }
+    }
+
+    fn feed(&mut self, mut input: &[u8], output: &mut Vec<u8>) {
+        if self.carry_length > 0 {
+            let count = input.len().min(3 - self.carry_length);
+            self.carry[self.carry_length..(self.carry_length + count)].copy_from_slice(&input[..count]);
+            self.carry_length += count;
+            input = &input[count..];
+
+            if self.carry_length < 3 {
+                return;
+            }
+
+            let group = self.carry;
+            self.encode_group(&group, output);
+            self.carry_length = 0;
+        }
+
+        let whole = input.len() / 3 * 3;
+        for group in input[..whole].chunks(3) {
+            self.encode_group(group, output);
+        }
+
+        let rest = &input[whole..];
+        self.carry[..rest.len()].copy_from_slice(rest);
+        self.carry_length = rest.len();
+    }
+
+    /// Encodes the carried bytes as the final, padded group.
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        if self.carry_length > 0 {
+            let group = self.carry;
+            self.encode_group(&group[..self.carry_length], output);
+            self.carry_length = 0;
+        }
+    }
+
+    fn encode_group(&mut self, group: &[u8], output: &mut Vec<u8>) {
+        let characters = self.engine.alphabet.characters();
         let mut word: u32 = 0;
 
-        for (index, byte) in chunk.iter().enumerate() {
-            let bitshift = 24 - (index * 8);
+        for (index, byte) in group.iter().enumerate() {
+            let bitshift = 16 - (index * 8);
             word |= (*byte as u32) << bitshift;
         }
 
-        output.push(encode_byte((word >> 26) as u8 & 0b00111111));
-        output.push(encode_byte((word >> 20) as u8 & 0b00111111));
+        // One byte fills two characters, two fill three.
+        for index in 0..(group.len() + 1) {
+            self.push(characters[(word >> (18 - index * 6)) as usize & 0b00111111], output);
+        }
+
+        if self.engine.padding {
+            for _ in group.len()..3 {
+                self.push(b'=', output);
+            }
+        }
+    }
 
-        match chunk.len() {
-            1 => {
-                output.push(b'=');
-                output.push(b'=');
-            },
-            2 => {
-                output.push(encode_byte((word >> 14) as u8 & 0b00111111));
-                output.push(b'=');
+    fn push(&mut self, character: u8, output: &mut Vec<u8>) {
+        if let Some(length) = self.engine.line_length {
+            if length > 0 && self.column == length {
+                output.extend_from_slice(b"\r\n");
+                self.column = 0;
+            }
+        }
+
+        output.push(character);
+        self.column += 1;
+    }
+}
+
+/// Encodes with the standard alphabet and padding.
+pub fn encode(input: &[u8]) -> Vec<u8> {
+    STANDARD.encode(input)
+}
+
+/// Where and why base64 input couldn't be decoded. Offsets count
+/// bytes from the start of the input, including any whitespace
+/// skipped in lenient mode.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DecodeError {
+    /// A byte outside the alphabet, or whitespace in strict mode.
+    InvalidByte { offset: usize, byte: u8 },
+    /// Padding where it can't go, the wrong amount of it, data after
+    /// it, or in strict mode, padding missing from the end.
+    InvalidPadding { offset: usize },
+    /// The input ends one character into a group, which isn't enough
+    /// to hold a byte.
+    InvalidLength { offset: usize },
+    /// The final character has bits set that an encoder would have
+    /// left as zero, so the input isn't the one encoding of its bytes.
+    NonCanonical { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::InvalidByte { offset, byte } => write!(formatter, "invalid byte 0x{:02x} at offset {}", byte, offset),
+            DecodeError::InvalidPadding { offset } => write!(formatter, "invalid padding at offset {}", offset),
+            DecodeError::InvalidLength { offset } => write!(formatter, "input ends partway through a byte at offset {}", offset),
+            DecodeError::NonCanonical { offset } => write!(formatter, "non-zero trailing bits at offset {}", offset),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+/// How forgiving `decode` is.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Mode {
+    /// Exactly the encoding the engine produces: only alphabet
+    /// characters, padded or not as the engine says, with the unused
+    /// bits of the last character set to zero.
+    Strict,
+    /// Skips whitespace and line breaks, and accepts input without its
+    /// padding or with non-zero trailing bits. Bytes outside the
+    /// alphabet and misplaced padding are still errors.
+    Lenient,
+}
+
+/// Decodes input a piece at a time, so a group of four characters may
+/// be split across calls to `feed`.
+struct Decoder {
+    engine: Engine,
+    mode: Mode,
+    /// Offset of the next byte fed in.
+    offset: usize,
+    /// Up to three sextets of the group being read.
+    group: [u8; 3],
+    length: usize,
+    /// Offset of the last alphabet character read.
+    last_offset: usize,
+    /// Padding characters read so far, and the offset of the first.
+    padding: usize,
+    padding_offset: usize,
+}
+
+impl Decoder {
+    fn new(engine: Engine, mode: Mode) -> Decoder {
+        Decoder { engine, mode, offset: 0, group: [0; 3], length: 0, last_offset: 0, padding: 0, padding_offset: 0 }
+    }
+
+    fn feed(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), DecodeError> {
+        for &byte in input {
+            let offset = self.offset;
+            self.offset += 1;
+
+            if self.skips(byte) {
+                continue;
+            }
+
+            if byte == b'=' {
+                if self.mode == Mode::Strict && !self.engine.padding {
+                    return Err(DecodeError::InvalidPadding { offset });
+                }
+
+                // Padding only ever fills out the last two or one
+                // characters of a group.
+                if self.length < 2 || self.length + self.padding >= 4 {
+                    return Err(DecodeError::InvalidPadding { offset });
+                }
+                if self.padding == 0 {
+                    self.padding_offset = offset;
+                }
+                self.padding += 1;
+                continue;
+            }
+
+            if self.padding > 0 {
+                return Err(DecodeError::InvalidPadding { offset });
+            }
+
+            let value = self.engine.alphabet.value(byte).ok_or(DecodeError::InvalidByte { offset, byte })?;
+            self.last_offset = offset;
+
+            if self.length < 3 {
+                self.group[self.length] = value;
+                self.length += 1;
+                continue;
+            }
+
+            let word = (self.group[0] as u32) << 18 | (self.group[1] as u32) << 12 | (self.group[2] as u32) << 6 | value as u32;
+            output.extend_from_slice(&[(word >> 16) as u8, (word >> 8) as u8, word as u8]);
+            self.length = 0;
+        }
+
+        Ok(())
+    }
+
+    fn skips(&self, byte: u8) -> bool {
+        match self.mode {
+            Mode::Lenient => is_whitespace(byte),
+            Mode::Strict => self.engine.line_length.is_some() && (byte == b'\r' || byte == b'\n'),
+        }
+    }
+
+    /// Decodes what's left of the final group.
+    fn finish(&mut self, output: &mut Vec<u8>) -> Result<(), DecodeError> {
+        let strict = self.mode == Mode::Strict;
+
+        match self.length {
+            0 => return Ok(()),
+            1 => return Err(DecodeError::InvalidLength { offset: self.offset }),
+            _ => {},
+        }
+
+        if self.padding > 0 && self.length + self.padding != 4 {
+            return Err(DecodeError::InvalidPadding { offset: self.padding_offset });
+        }
+
+        if strict && self.engine.padding && self.padding == 0 {
+            return Err(DecodeError::InvalidPadding { offset: self.offset });
+        }
+
+        let word = (self.group[0] as u32) << 18 | (self.group[1] as u32) << 12 | (self.group[2] as u32) << 6;
+        let (bytes, unused_bits) = if self.length == 2 { (1, word & 0xF000) } else { (2, word & 0xC0) };
+
+        if strict && unused_bits != 0 {
+            return Err(DecodeError::NonCanonical { offset: self.last_offset });
+        }
+
+        output.extend_from_slice(&[(word >> 16) as u8, (word >> 8) as u8][..bytes]);
+        self.length = 0;
+        Ok(())
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t' || byte == b'\r' || byte == b'\n'
+}
+
+/// Decodes base64 from the standard alphabet, with padding.
+pub fn decode(input: &[u8], mode: Mode) -> Result<Vec<u8>, DecodeError> {
+    STANDARD.decode(input, mode)
+}
+
+/// Encodes everything written to it before passing it on to the inner
+/// writer, so a large file can be copied into a socket as base64
+/// without being held in memory. Up to two bytes are held back until
+/// the rest of their group is written. `finish` encodes them and the
+/// padding; dropping the writer does the same but can't report errors.
+///
+/// Once a write has been accepted, any of its output the inner writer
+/// fails to take is kept and written ahead of whatever comes next, so
+/// nothing is lost or encoded twice when the caller retries.
+pub struct EncoderWriter<W: Write> {
+    inner: Option<W>,
+    encoder: Encoder,
+    /// Encoded output the inner writer hasn't taken yet.
+    buffer: Vec<u8>,
+    /// The final group and padding have been added to the buffer.
+    finished: bool,
+}
+
+impl<W: Write> EncoderWriter<W> {
+    pub fn new(inner: W, engine: Engine) -> EncoderWriter<W> {
+        EncoderWriter { inner: Some(inner), encoder: Encoder::new(engine), buffer: Vec::new(), finished: false }
+    }
+
+    /// Writes the final group and returns the inner writer. The final
+    /// group is only encoded once, however often this has to be tried.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_final()?;
+        Ok(self.inner.take().expect("EncoderWriter already finished"))
+    }
+
+    fn write_final(&mut self) -> io::Result<()> {
+        if let Some(ref mut inner) = self.inner {
+            if !self.finished {
+                self.encoder.finish(&mut self.buffer);
+                self.finished = true;
+            }
+
+            write_buffered(inner, &mut self.buffer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes out the buffer, removing whatever the writer takes, so that
+/// after an error only the rest is left.
+fn write_buffered<W: Write>(inner: &mut W, buffer: &mut Vec<u8>) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match inner.write(buffer) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write encoded output")),
+            Ok(count) => {
+                buffer.drain(..count);
             },
-            3 => {
-                output.push(encode_byte((word >> 14) as u8 & 0b00111111));
-                output.push(encode_byte((word >> 8) as u8 & 0b00111111));
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+impl<W: Write> Write for EncoderWriter<W> {
+    /// Output left over from an earlier write goes out first, and if
+    /// that fails none of the input is taken. Once the input has been
+    /// encoded it counts as written, and an error passing on its output
+    /// is reported by the next call instead.
+    fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+        let inner = self.inner.as_mut().expect("EncoderWriter already finished");
+
+        write_buffered(inner, &mut self.buffer)?;
+
+        self.encoder.feed(input, &mut self.buffer);
+        let _ = write_buffered(inner, &mut self.buffer);
+
+        Ok(input.len())
+    }
+
+    /// Writes out any output left over from an earlier write and
+    /// flushes the inner writer. Held back bytes stay held back, since
+    /// encoding them early would put padding in the middle.
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Some(ref mut inner) => {
+                write_buffered(inner, &mut self.buffer)?;
+                inner.flush()
             },
-            _ => panic!(),
-        };
+            None => Ok(()),
+        }
+    }
+}
 
+impl<W: Write> Drop for EncoderWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_final();
     }
+}
 
-    output
+/// Decodes base64 read from the inner reader, so an upload can be
+/// decoded into a file as it arrives. Malformed input is reported as
+/// an `InvalidData` error holding the `DecodeError`, with offsets
+/// counted from the first byte read.
+pub struct DecoderReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecoderReader<R> {
+    pub fn new(inner: R, engine: Engine, mode: Mode) -> DecoderReader<R> {
+        DecoderReader {
+            inner,
+            decoder: Decoder::new(engine, mode),
+            input: vec![0; READ_CHUNK_SIZE],
+            output: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DecoderReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.output.len() {
+                let count = buffer.len().min(self.output.len() - self.position);
+                buffer[..count].copy_from_slice(&self.output[self.position..(self.position + count)]);
+                self.position += count;
+                return Ok(count);
+            }
+
+            if self.finished || buffer.is_empty() {
+                return Ok(0);
+            }
+
+            self.output.clear();
+            self.position = 0;
+
+            let count = match self.inner.read(&mut self.input) {
+                Ok(count) => count,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+
+            let result = if count == 0 {
+                self.finished = true;
+                self.decoder.finish(&mut self.output)
+            } else {
+                self.decoder.feed(&self.input[..count], &mut self.output)
+            };
+
+            result.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -121,4 +516,251 @@ mod tests {
         assert_eq!(&encode(b"foob"), b"Zm9vYg==");
         assert_eq!(&encode(b"fooba"), b"Zm9vYmE=");
     }
+
+    #[test]
+    fn decode_passes_test_cases_from_rfc4648() {
+        let cases: [(&[u8], &[u8]); 7] = [(b"", b""), (b"Zg==", b"f"), (b"Zm8=", b"fo"), (b"Zm9v", b"foo"),
+                                          (b"Zm9vYg==", b"foob"), (b"Zm9vYmE=", b"fooba"), (b"Zm9vYmFy", b"foobar")];
+
+        for &(encoded, decoded) in &cases {
+            assert_eq!(decode(encoded, Mode::Strict).unwrap(), decoded);
+            assert_eq!(decode(encoded, Mode::Lenient).unwrap(), decoded);
+        }
+    }
+
+    #[test]
+    fn decode_round_trips_every_byte() {
+        let input: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&input), Mode::Strict).unwrap(), input);
+    }
+
+    #[test]
+    fn decode_strict_rejects_bytes_outside_alphabet() {
+        assert_eq!(decode(b"Zm9v!mFy", Mode::Strict), Err(DecodeError::InvalidByte { offset: 4, byte: b'!' }));
+        assert_eq!(decode(b"Zm9v\nYmFy", Mode::Strict), Err(DecodeError::InvalidByte { offset: 4, byte: b'\n' }));
+        assert_eq!(decode(b"Zm9v-mFy", Mode::Lenient), Err(DecodeError::InvalidByte { offset: 4, byte: b'-' }));
+    }
+
+    #[test]
+    fn decode_strict_rejects_bad_padding() {
+        assert_eq!(decode(b"Zg", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 2 }));
+        assert_eq!(decode(b"Zg=", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 2 }));
+        assert_eq!(decode(b"Zm9v=", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 4 }));
+        assert_eq!(decode(b"Z===", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 1 }));
+        assert_eq!(decode(b"Zm8==", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 4 }));
+        assert_eq!(decode(b"Zg==Zg==", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 4 }));
+    }
+
+    #[test]
+    fn decode_rejects_a_lone_final_character() {
+        assert_eq!(decode(b"Zm9vY", Mode::Strict), Err(DecodeError::InvalidLength { offset: 5 }));
+        assert_eq!(decode(b"Zm9vY", Mode::Lenient), Err(DecodeError::InvalidLength { offset: 5 }));
+    }
+
+    #[test]
+    fn decode_strict_rejects_non_canonical_trailing_bits() {
+        assert_eq!(decode(b"Zh==", Mode::Strict), Err(DecodeError::NonCanonical { offset: 1 }));
+        assert_eq!(decode(b"Zm9=", Mode::Strict), Err(DecodeError::NonCanonical { offset: 2 }));
+        assert_eq!(decode(b"Zh==", Mode::Lenient), Ok(b"f".to_vec()));
+    }
+
+    #[test]
+    fn decode_lenient_skips_whitespace_and_missing_padding() {
+        assert_eq!(decode(b" Zm9v\r\nYmFy\n", Mode::Lenient), Ok(b"foobar".to_vec()));
+        assert_eq!(decode(b"Zm9vYg", Mode::Lenient), Ok(b"foob".to_vec()));
+        assert_eq!(decode(b"Zm9vYg =\n=", Mode::Lenient), Ok(b"foob".to_vec()));
+        assert_eq!(decode(b"Zg==\n", Mode::Lenient), Ok(b"f".to_vec()));
+    }
+
+    #[test]
+    fn url_safe_uses_dash_and_underscore() {
+        assert_eq!(&URL_SAFE.encode(&[0xfb, 0xff]), b"-_8=");
+        assert_eq!(&URL_SAFE_NO_PAD.encode(&[0xfb, 0xff]), b"-_8");
+        assert_eq!(&STANDARD.encode(&[0xfb, 0xff]), b"+/8=");
+        assert_eq!(URL_SAFE_NO_PAD.decode(b"-_8", Mode::Strict), Ok(vec![0xfb, 0xff]));
+        assert_eq!(URL_SAFE.decode(b"+/8=", Mode::Lenient), Err(DecodeError::InvalidByte { offset: 0, byte: b'+' }));
+    }
+
+    #[test]
+    fn no_pad_leaves_off_padding() {
+        assert_eq!(&STANDARD_NO_PAD.encode(b"f"), b"Zg");
+        assert_eq!(&STANDARD_NO_PAD.encode(b"fo"), b"Zm8");
+        assert_eq!(&STANDARD_NO_PAD.encode(b"foo"), b"Zm9v");
+        assert_eq!(STANDARD_NO_PAD.decode(b"Zm8", Mode::Strict), Ok(b"fo".to_vec()));
+        assert_eq!(STANDARD_NO_PAD.decode(b"Zm8=", Mode::Strict), Err(DecodeError::InvalidPadding { offset: 3 }));
+        assert_eq!(STANDARD_NO_PAD.decode(b"Zm8=", Mode::Lenient), Ok(b"fo".to_vec()));
+    }
+
+    #[test]
+    fn url_safe_no_pad_encodes_jwt_header() {
+        // The JOSE header from Section 3.1 of RFC 7515.
+        let header = b"{\"typ\":\"JWT\",\r\n \"alg\":\"HS256\"}";
+        assert_eq!(&URL_SAFE_NO_PAD.encode(header), b"eyJ0eXAiOiJKV1QiLA0KICJhbGciOiJIUzI1NiJ9");
+    }
+
+    #[test]
+    fn mime_wraps_lines_at_76_characters() {
+        let input = [0u8; 120];
+        let encoded = MIME.encode(&input);
+        let lines: Vec<&[u8]> = encoded.split(|byte| *byte == b'\n').collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 77);
+        assert!(lines[0].ends_with(b"\r"));
+        assert_eq!(lines[2].len(), 8);
+        assert_eq!(&MIME.encode(&input[..57]), &STANDARD.encode(&input[..57]));
+        assert_eq!(MIME.decode(&encoded, Mode::Strict).unwrap(), &input[..]);
+        assert_eq!(STANDARD.decode(&encoded, Mode::Strict), Err(DecodeError::InvalidByte { offset: 76, byte: b'\r' }));
+    }
+
+    /// Reads at most `size` bytes at a time, to split groups across
+    /// reads.
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let count = self.0.len().min(self.1).min(buffer.len());
+            buffer[..count].copy_from_slice(&self.0[..count]);
+            self.0 = &self.0[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn encoder_writer_matches_encode_whatever_the_write_sizes() {
+        let input: Vec<u8> = (0..200).map(|byte| byte as u8).collect();
+
+        for &engine in &[STANDARD, URL_SAFE_NO_PAD, MIME] {
+            for size in 1..8 {
+                let mut writer = EncoderWriter::new(Vec::new(), engine);
+                for chunk in input.chunks(size) {
+                    writer.write_all(chunk).unwrap();
+                }
+
+                assert_eq!(writer.finish().unwrap(), engine.encode(&input));
+            }
+        }
+    }
+
+    #[test]
+    fn encoder_writer_finishes_when_dropped() {
+        let mut output = Vec::new();
+        {
+            let mut writer = EncoderWriter::new(&mut output, STANDARD);
+            writer.write_all(b"fooba").unwrap();
+        }
+
+        assert_eq!(&output, b"Zm9vYmE=");
+    }
+
+    /// Fails the writes whose numbers, counting from one, are listed,
+    /// and takes at most two bytes from the rest.
+    struct Flaky {
+        output: Vec<u8>,
+        writes: usize,
+        failing: Vec<usize>,
+    }
+
+    impl Flaky {
+        fn new(failing: Vec<usize>) -> Flaky {
+            Flaky { output: Vec::new(), writes: 0, failing }
+        }
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+
+            if self.failing.contains(&self.writes) {
+                return Err(io::Error::other("flaky"));
+            }
+
+            let count = input.len().min(2);
+            self.output.extend_from_slice(&input[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encoder_writer_keeps_output_the_inner_writer_fails_to_take() {
+        let mut flaky = Flaky::new((1..30).filter(|write| write % 3 == 0).collect());
+        let input = b"the quick brown fox jumps over the lazy dog";
+
+        let mut writer = EncoderWriter::new(&mut flaky, STANDARD);
+        let mut rest = &input[..];
+
+        while !rest.is_empty() {
+            if let Ok(count) = writer.write(&rest[..rest.len().min(5)]) {
+                rest = &rest[count..];
+            }
+        }
+
+        while writer.flush().is_err() {}
+        writer.finish().unwrap();
+
+        assert_eq!(flaky.output, STANDARD.encode(input));
+    }
+
+    #[test]
+    fn encoder_writer_pads_once_when_finish_fails() {
+        // "Zm9v" goes out in two writes, then "Ym" of the final group
+        // before the fourth write fails.
+        let mut flaky = Flaky::new(vec![4]);
+
+        {
+            let mut writer = EncoderWriter::new(&mut flaky, STANDARD);
+            writer.write_all(b"fooba").unwrap();
+            assert!(writer.finish().is_err());
+        }
+
+        assert_eq!(&flaky.output, b"Zm9vYmE=");
+    }
+
+    #[test]
+    fn decoder_reader_matches_decode_whatever_the_read_sizes() {
+        let input: Vec<u8> = (0..200).map(|byte| byte as u8).collect();
+        let encoded = MIME.encode(&input);
+
+        for size in 1..8 {
+            let mut output = Vec::new();
+            DecoderReader::new(Trickle(&encoded, size), MIME, Mode::Strict).read_to_end(&mut output).unwrap();
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn decoder_reader_reports_errors_as_invalid_data() {
+        let mut reader = DecoderReader::new(Trickle(b"Zm9vYmFy!", 3), STANDARD, Mode::Strict);
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.get_ref().unwrap().to_string(), "invalid byte 0x21 at offset 8");
+
+        let mut reader = DecoderReader::new(&b"Zm9vYg"[..], STANDARD, Mode::Strict);
+        assert_eq!(reader.read_to_end(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn wrappers_compose_with_files() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::process;
+
+        let path = env::temp_dir().join(format!("strudel-base64-{}", process::id()));
+        let input: Vec<u8> = (0..100_000).map(|index| (index * 7) as u8).collect();
+
+        let mut writer = EncoderWriter::new(File::create(&path).unwrap(), MIME);
+        io::copy(&mut &input[..], &mut writer).unwrap();
+        writer.finish().unwrap();
+
+        let mut output = Vec::new();
+        DecoderReader::new(File::open(&path).unwrap(), MIME, Mode::Strict).read_to_end(&mut output).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(output, input);
+    }
 }
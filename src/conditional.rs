@@ -0,0 +1,133 @@
+use date::parse_http_date;
+use request::{Method, Request};
+use response::{Response, Status};
+use digest::Digest;
+use sha1::SHA1Context;
+
+/// The headers a 304 response repeats from the 200 it stands in for,
+/// from Section 4.1 of RFC 7232. Date and Server are filled in when it
+/// is written.
+const KEPT_HEADERS: [&str; 6] = ["Cache-Control", "Content-Location", "ETag", "Expires", "Last-Modified", "Vary"];
+
+/// A strong entity tag for a representation: the SHA-1 of its bytes in
+/// hex, in quotes as Section 2.3 of RFC 7232 requires.
+pub fn etag(content: &[u8]) -> String {
+    format_etag(SHA1Context::hash(content))
+}
+
+fn format_etag(digest: [u8; 20]) -> String {
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Turns a 200 response to a GET or HEAD into a 304 Not Modified when
+/// the client's cached copy is still current, going by the response's
+/// ETag and Last-Modified headers. If-None-Match is checked first and,
+/// when present, If-Modified-Since is ignored, as Section 6 of RFC 7232
+/// lays out. Any other response is returned as it is.
+pub fn check(request: &Request, response: Response) -> Response {
+    if response.status != Status::Ok || (request.method != Method::Get && request.method != Method::Head) {
+        return response;
+    }
+
+    let fresh = match request.headers.get("if-none-match") {
+        Some(tags) => response.header("ETag").is_some_and(|etag| matches_any(tags, etag)),
+        None => match (request.headers.get("if-modified-since"), response.header("Last-Modified")) {
+            (Some(since), Some(modified)) => match (parse_http_date(since), parse_http_date(modified)) {
+                (Some(since), Some(modified)) => modified <= since,
+                _ => false,
+            },
+            _ => false,
+        },
+    };
+
+    if !fresh {
+        return response;
+    }
+
+    let mut not_modified = Response::new(Status::NotModified);
+    for name in &KEPT_HEADERS {
+        if let Some(value) = response.header(name) {
+            not_modified.set_header(name, value);
+        }
+    }
+
+    not_modified
+}
+
+/// Whether an If-None-Match list names the tag. This is the weak
+/// comparison from Section 2.3.2 of RFC 7232, so a `W/` prefix on
+/// either side is ignored.
+fn matches_any(tags: &str, etag: &str) -> bool {
+    let tags = tags.trim();
+
+    if tags == "*" {
+        return true;
+    }
+
+    let etag = etag.trim_start_matches("W/");
+    tags.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use request::parse_request;
+
+    const ETAG: &str = "\"2aae6c35c94fcfb415dbe95f408b9ce91ee846ed\"";
+    const MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn page() -> Response {
+        Response::new(Status::Ok)
+            .with_header("Content-Type", "text/html")
+            .with_header("ETag", ETAG)
+            .with_header("Last-Modified", MODIFIED)
+            .with_body("hello world")
+    }
+
+    fn status_for(request: &[u8]) -> u16 {
+        check(&parse_request(request).unwrap(), page()).status.code()
+    }
+
+    #[test]
+    fn etag_is_quoted_sha1_hex() {
+        assert_eq!(etag(b"hello world"), ETAG);
+    }
+
+    #[test]
+    fn check_returns_304_when_if_none_match_matches() {
+        let request = format!("GET / HTTP/1.1\r\nIf-None-Match: \"other\", W/{}\r\n\r\n", ETAG);
+        let response = check(&parse_request(request.as_bytes()).unwrap(), page());
+
+        assert_eq!(response.status, Status::NotModified);
+        assert_eq!(response.header("ETag"), Some(ETAG));
+        assert_eq!(response.header("Last-Modified"), Some(MODIFIED));
+        assert_eq!(response.header("Content-Type"), None);
+        assert_eq!(status_for(b"GET / HTTP/1.1\r\nIf-None-Match: *\r\n\r\n"), 304);
+    }
+
+    #[test]
+    fn check_returns_200_when_if_none_match_differs() {
+        assert_eq!(status_for(b"GET / HTTP/1.1\r\nIf-None-Match: \"other\"\r\n\r\n"), 200);
+    }
+
+    #[test]
+    fn check_compares_if_modified_since_with_last_modified() {
+        assert_eq!(status_for(b"GET / HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"), 304);
+        assert_eq!(status_for(b"HEAD / HTTP/1.1\r\nIf-Modified-Since: Mon, 07 Nov 1994 00:00:00 GMT\r\n\r\n"), 304);
+        assert_eq!(status_for(b"GET / HTTP/1.1\r\nIf-Modified-Since: Sat, 05 Nov 1994 00:00:00 GMT\r\n\r\n"), 200);
+        assert_eq!(status_for(b"GET / HTTP/1.1\r\nIf-Modified-Since: not a date\r\n\r\n"), 200);
+    }
+
+    #[test]
+    fn check_ignores_if_modified_since_when_if_none_match_is_present() {
+        let request = b"GET / HTTP/1.1\r\nIf-None-Match: \"other\"\r\nIf-Modified-Since: Mon, 07 Nov 1994 00:00:00 GMT\r\n\r\n";
+        assert_eq!(status_for(request), 200);
+    }
+
+    #[test]
+    fn check_only_applies_to_get_and_head() {
+        let request = format!("POST / HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", ETAG);
+        assert_eq!(status_for(request.as_bytes()), 200);
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats a time as an IMF-fixdate, such as
+/// "Sun, 06 Nov 1994 08:49:37 GMT", the form Section 7.1.1.1 of
+/// RFC 7231 requires for the Date header and other timestamps.
+/// Times before 1970 are written as the epoch.
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let days = seconds / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let seconds_today = seconds % 86400;
+
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAY_NAMES[(days % 7) as usize],
+            day,
+            MONTH_NAMES[(month - 1) as usize],
+            year,
+            seconds_today / 3600,
+            seconds_today / 60 % 60,
+            seconds_today % 60)
+}
+
+/// Parses a timestamp in any of the three formats Section 7.1.1.1 of
+/// RFC 7231 asks recipients to accept: the IMF-fixdate, the obsolete
+/// RFC 850 format and ANSI C's asctime() format. Returns None for
+/// anything else, including times before 1970.
+pub fn parse_http_date(text: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+
+    let (day, month, year, time) = match tokens.len() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        6 if tokens[0].ends_with(',') && tokens[5] == "GMT" => {
+            (tokens[1], tokens[2], parse_number(tokens[3], 4)?, tokens[4])
+        },
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        4 if tokens[0].ends_with(',') && tokens[3] == "GMT" => {
+            let parts: Vec<&str> = tokens[1].split('-').collect();
+            if parts.len() != 3 {
+                return None;
+            }
+            // Two-digit years are taken to be in 1970 to 2069.
+            let year = parse_number(parts[2], 2)?;
+            (parts[0], parts[1], if year < 70 { 2000 + year } else { 1900 + year }, tokens[2])
+        },
+        // Sun Nov  6 08:49:37 1994
+        5 => (tokens[2], tokens[1], parse_number(tokens[4], 4)?, tokens[3]),
+        _ => return None,
+    };
+
+    let month = MONTH_NAMES.iter().position(|name| *name == month)? as u32 + 1;
+    let day = match day.len() {
+        1 | 2 => parse_number(day, day.len())? as u32,
+        _ => return None,
+    };
+
+    let parts: Vec<&str> = time.split(':').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let (hour, minute, second) = (parse_number(parts[0], 2)?, parse_number(parts[1], 2)?, parse_number(parts[2], 2)?);
+
+    if year < 1970 || day == 0 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64))
+}
+
+/// Parses exactly `digits` ASCII digits.
+fn parse_number(text: &str, digits: usize) -> Option<i64> {
+    if text.len() != digits || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    text.parse().ok()
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts a year, month and day to a count of days since 1970-01-01,
+/// the inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Converts a count of days since 1970-01-01 to a year, month and day,
+/// using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn format_http_date_matches_rfc7231_example() {
+        assert_eq!(format_http_date(at(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn format_http_date_handles_epoch_and_leap_days() {
+        assert_eq!(format_http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format_http_date(at(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(format_http_date(at(4107542400)), "Mon, 01 Mar 2100 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parse_http_date_accepts_all_three_formats() {
+        let expected = Some(at(784111777));
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+    }
+
+    #[test]
+    fn parse_http_date_round_trips_formatted_dates() {
+        for seconds in &[0, 951782400, 4107542400, 1700000000] {
+            assert_eq!(parse_http_date(&format_http_date(at(*seconds))), Some(at(*seconds)));
+        }
+    }
+
+    #[test]
+    fn parse_http_date_rejects_malformed_dates() {
+        for text in &["", "yesterday", "Sun, 06 Nov 1994 08:49:37 PST", "Sun, 31 Nov 1994 08:49:37 GMT",
+                      "Sun, 06 Nov 1994 24:00:00 GMT", "Sun, 06 Nov 94 08:49:37 GMT", "Sat, 01 Jan 1960 00:00:00 GMT"] {
+            assert_eq!(parse_http_date(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,771 @@
+/// The largest back-reference distance DEFLATE allows, which is also
+/// how much history a decompressor has to keep.
+const MAX_WINDOW_SIZE: usize = 32 * 1024;
+
+const MAX_CODE_LENGTH: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// How many earlier positions with the same hash the compressor looks
+/// at before settling for the best match found so far.
+const MAX_CHAIN_LENGTH: usize = 128;
+
+const HASH_BITS: usize = 15;
+const NO_POSITION: usize = usize::MAX;
+
+/// The largest amount of data a stored block may hold.
+const MAX_STORED_LENGTH: usize = 0xFFFF;
+
+/// Base lengths and extra bits for length codes 257-285, from
+/// Section 3.2.5 of RFC 1951.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances and extra bits for distance codes 0-29.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// The order code length code lengths are sent in for dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+#[derive(PartialEq, Debug)]
+pub enum InflateError {
+    InvalidData,
+    TooLarge,
+}
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> BitReader<'a> {
+        BitReader { input, position: 0, bit: 0 }
+    }
+
+    /// Reads `count` bits, least significant bit first, as described
+    /// in Section 3.1.1 of RFC 1951.
+    fn bits(&mut self, count: u8) -> Result<u32, InflateError> {
+        let mut value = 0;
+
+        for index in 0..count {
+            if self.position >= self.input.len() {
+                return Err(InflateError::InvalidData);
+            }
+
+            let bit = (self.input[self.position] >> self.bit) & 1;
+            value |= (bit as u32) << index;
+
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Skips to the start of the next byte.
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position >= self.input.len()
+    }
+}
+
+/// A canonical Huffman code stored as the number of codes of each
+/// length along with the symbols in code order.
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the length of each symbol's code, where a
+    /// length of zero means the symbol is unused. Incomplete codes are
+    /// allowed, since a distance code may have only one symbol, but
+    /// over-subscribed ones are not.
+    fn new(lengths: &[u8]) -> Result<Huffman, InflateError> {
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+
+        let mut left: i32 = 1;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(InflateError::InvalidData);
+            }
+        }
+
+        let mut offsets = [0; MAX_CODE_LENGTH + 2];
+        for length in 1..=MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + counts[length] as usize;
+        }
+
+        let mut symbols = vec![0; offsets[MAX_CODE_LENGTH + 1]];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize]] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Decodes one symbol a bit at a time. Codes of each length are
+    /// consecutive integers, so the code is found once it falls within
+    /// the range for its length.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, InflateError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for count in self.counts.iter().skip(1) {
+            code |= reader.bits(1)? as i32;
+            let count = *count as i32;
+
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(InflateError::InvalidData)
+    }
+}
+
+fn fixed_literal_lengths() -> [u8; 288] {
+    let mut lengths = [0; 288];
+
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    lengths
+}
+
+/// Decompresses raw DEFLATE data (RFC 1951) that may arrive as a
+/// series of separately flushed pieces, as with permessage-deflate.
+/// The most recent output is kept so later pieces can refer back to
+/// it.
+pub struct Inflater {
+    window: Vec<u8>,
+}
+
+impl Default for Inflater {
+    fn default() -> Inflater {
+        Inflater::new()
+    }
+}
+
+impl Inflater {
+    pub fn new() -> Inflater {
+        Inflater { window: Vec::new() }
+    }
+
+    /// Forgets earlier output so the next piece must stand on its own.
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+
+    /// Decodes blocks until the input runs out or a block marked final
+    /// is finished. The input must end on a block boundary. Fails with
+    /// TooLarge rather than produce more than `max_length` bytes.
+    pub fn inflate(&mut self, input: &[u8], max_length: usize) -> Result<Vec<u8>, InflateError> {
+        let mut reader = BitReader::new(input);
+        let mut output = Vec::new();
+        output.append(&mut self.window);
+        let start = output.len();
+
+        while !reader.is_at_end() {
+            let is_final = reader.bits(1)? == 1;
+
+            match reader.bits(2)? {
+                0 => inflate_stored(&mut reader, &mut output)?,
+                1 => {
+                    let literals = Huffman::new(&fixed_literal_lengths())?;
+                    let distances = Huffman::new(&[5; 30])?;
+                    inflate_codes(&mut reader, &mut output, &literals, &distances, start + max_length)?;
+                },
+                2 => {
+                    let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                    inflate_codes(&mut reader, &mut output, &literals, &distances, start + max_length)?;
+                },
+                _ => return Err(InflateError::InvalidData),
+            }
+
+            if output.len() - start > max_length {
+                return Err(InflateError::TooLarge);
+            }
+
+            if is_final {
+                break;
+            }
+        }
+
+        let result = output[start..].to_vec();
+        let keep_from = output.len().saturating_sub(MAX_WINDOW_SIZE);
+        self.window = output.split_off(keep_from);
+
+        Ok(result)
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), InflateError> {
+    reader.align();
+
+    let position = reader.position;
+    if reader.input.len() < position + 4 {
+        return Err(InflateError::InvalidData);
+    }
+
+    let header = &reader.input[position..(position + 4)];
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+
+    if length != !complement {
+        return Err(InflateError::InvalidData);
+    }
+
+    let start = position + 4;
+    let end = start + length as usize;
+    if reader.input.len() < end {
+        return Err(InflateError::InvalidData);
+    }
+
+    output.extend_from_slice(&reader.input[start..end]);
+    reader.position = end;
+
+    Ok(())
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    if literal_count > 286 || distance_count > 30 {
+        return Err(InflateError::InvalidData);
+    }
+
+    let mut code_length_lengths = [0; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_length_lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+
+    while index < lengths.len() {
+        let symbol = code_lengths.decode(reader)?;
+
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(InflateError::InvalidData);
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if index + repeat > lengths.len() {
+            return Err(InflateError::InvalidData);
+        }
+
+        for length_slot in &mut lengths[index..(index + repeat)] {
+            *length_slot = length;
+        }
+        index += repeat;
+    }
+
+    // Without a code for end-of-block the block could never finish.
+    if lengths[256] == 0 {
+        return Err(InflateError::InvalidData);
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+
+    Ok((literals, distances))
+}
+
+fn inflate_codes(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, limit: usize) -> Result<(), InflateError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(InflateError::InvalidData);
+            }
+            let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol])? as usize;
+
+            let symbol = distances.decode(reader)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(InflateError::InvalidData);
+            }
+            let distance = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol])? as usize;
+
+            if distance > output.len() {
+                return Err(InflateError::InvalidData);
+            }
+
+            // The copy may overlap the bytes it produces, so it has to
+            // go one byte at a time.
+            let from = output.len() - distance;
+            for offset in 0..length {
+                let byte = output[from + offset];
+                output.push(byte);
+            }
+        }
+
+        if output.len() > limit {
+            return Err(InflateError::TooLarge);
+        }
+    }
+}
+
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { output: Vec::new(), buffer: 0, count: 0 }
+    }
+
+    /// Writes the low `count` bits of value, least significant first.
+    fn bits(&mut self, value: u32, count: u8) {
+        self.buffer |= value << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting with their most significant
+    /// bit, the reverse of every other field.
+    fn code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length);
+        self.bits(reversed as u32, length);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.bits(0, 8 - self.count);
+        }
+    }
+}
+
+/// Returns the code and its length for a literal/length symbol in the
+/// fixed Huffman code from Section 3.2.6 of RFC 1951.
+fn fixed_literal_code(symbol: usize) -> (u16, u8) {
+    match symbol {
+        0..=143 => (0x30 + symbol as u16, 8),
+        144..=255 => (0x190 + (symbol - 144) as u16, 9),
+        256..=279 => ((symbol - 256) as u16, 7),
+        _ => (0xC0 + (symbol - 280) as u16, 8),
+    }
+}
+
+enum Token {
+    Literal(u8),
+    Match(usize, usize),
+}
+
+/// Compresses data as raw DEFLATE (RFC 1951). Each call ends with a
+/// sync flush, so the output so far can be decompressed on its own,
+/// and later calls may refer back to data from earlier ones.
+///
+/// Only fixed Huffman codes are produced. Most of the savings on the
+/// repetitive text we send come from the back-references, and the
+/// fixed code keeps the compressor small.
+pub struct Deflater {
+    window_size: usize,
+    history: Vec<u8>,
+}
+
+impl Deflater {
+    /// Creates a compressor that never refers back further than
+    /// 2^window_bits bytes. Window bits must be between 8 and 15.
+    pub fn new(window_bits: u8) -> Deflater {
+        Deflater { window_size: 1 << window_bits, history: Vec::new() }
+    }
+
+    /// Forgets earlier input so the next output stands on its own.
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    /// Compresses the input, ending with a sync flush: an empty stored
+    /// block followed by the bytes 00 00 FF FF.
+    pub fn deflate(&mut self, input: &[u8]) -> Vec<u8> {
+        let start = self.history.len();
+        let mut data = Vec::with_capacity(start + input.len());
+        data.append(&mut self.history);
+        data.extend_from_slice(input);
+
+        let tokens = self.tokenize(&data, start);
+
+        let mut writer = BitWriter::new();
+        if !input.is_empty() {
+            writer.bits(0, 1);
+            writer.bits(1, 2);
+
+            for token in &tokens {
+                match *token {
+                    Token::Literal(byte) => {
+                        let (code, length) = fixed_literal_code(byte as usize);
+                        writer.code(code, length);
+                    },
+                    Token::Match(length, distance) => write_match(&mut writer, length, distance),
+                }
+            }
+
+            let (code, length) = fixed_literal_code(256);
+            writer.code(code, length);
+
+            // Data that doesn't compress is sent as-is rather than
+            // growing by as much as an eighth.
+            if writer.output.len() > input.len() + 5 * input.len().div_ceil(MAX_STORED_LENGTH) {
+                writer = BitWriter::new();
+                for chunk in input.chunks(MAX_STORED_LENGTH) {
+                    write_stored(&mut writer, chunk);
+                }
+            }
+        }
+
+        write_stored(&mut writer, &[]);
+
+        let keep_from = data.len().saturating_sub(self.window_size);
+        self.history = data.split_off(keep_from);
+
+        writer.output
+    }
+
+    /// Splits `data[start..]` into literals and back-references.
+    /// Everything before `start` is history that may be referred to
+    /// but isn't output again.
+    fn tokenize(&self, data: &[u8], start: usize) -> Vec<Token> {
+        let mut chains = HashChains::new(data.len());
+        let mut tokens = Vec::new();
+
+        for position in 0..start {
+            chains.insert(data, position);
+        }
+
+        let mut position = start;
+        while position < data.len() {
+            let (length, distance) = chains.longest_match(data, position, self.window_size);
+
+            if length >= MIN_MATCH {
+                tokens.push(Token::Match(length, distance));
+                for offset in 0..length {
+                    chains.insert(data, position + offset);
+                }
+                position += length;
+            } else {
+                tokens.push(Token::Literal(data[position]));
+                chains.insert(data, position);
+                position += 1;
+            }
+        }
+
+        tokens
+    }
+}
+
+/// Links every position to the previous one starting with the same
+/// three bytes, so earlier matches can be found without searching the
+/// whole window.
+struct HashChains {
+    head: Vec<usize>,
+    previous: Vec<usize>,
+}
+
+impl HashChains {
+    fn new(length: usize) -> HashChains {
+        HashChains { head: vec![NO_POSITION; 1 << HASH_BITS], previous: vec![NO_POSITION; length] }
+    }
+
+    fn hash(data: &[u8], position: usize) -> usize {
+        let value = (data[position] as usize) << 10 ^ (data[position + 1] as usize) << 5 ^ data[position + 2] as usize;
+        value & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH <= data.len() {
+            let key = HashChains::hash(data, position);
+            self.previous[position] = self.head[key];
+            self.head[key] = position;
+        }
+    }
+
+    /// Returns the length and distance of the longest earlier match
+    /// for the data at `position` no further back than `window_size`.
+    fn longest_match(&self, data: &[u8], position: usize, window_size: usize) -> (usize, usize) {
+        if position + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+
+        let max_length = MAX_MATCH.min(data.len() - position);
+        let mut candidate = self.head[HashChains::hash(data, position)];
+        let mut best = (0, 0);
+
+        for _ in 0..MAX_CHAIN_LENGTH {
+            if candidate == NO_POSITION || position - candidate > window_size {
+                break;
+            }
+
+            let length = data[candidate..].iter()
+                .zip(&data[position..(position + max_length)])
+                .take_while(|&(a, b)| a == b)
+                .count();
+
+            if length > best.0 {
+                best = (length, position - candidate);
+                if length == max_length {
+                    break;
+                }
+            }
+
+            candidate = self.previous[candidate];
+        }
+
+        best
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let symbol = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap();
+    let (code, code_length) = fixed_literal_code(257 + symbol);
+    writer.code(code, code_length);
+    writer.bits((length - LENGTH_BASE[symbol] as usize) as u32, LENGTH_EXTRA[symbol]);
+
+    let symbol = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap();
+    writer.code(symbol as u16, 5);
+    writer.bits((distance - DISTANCE_BASE[symbol] as usize) as u32, DISTANCE_EXTRA[symbol]);
+}
+
+/// Writes a non-final stored block holding the data as-is.
+fn write_stored(writer: &mut BitWriter, data: &[u8]) {
+    writer.bits(0, 3);
+    writer.align();
+
+    let length = data.len() as u16;
+    writer.output.extend_from_slice(&length.to_le_bytes());
+    writer.output.extend_from_slice(&(!length).to_le_bytes());
+    writer.output.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYNC_FLUSH: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+    fn with_sync_flush(data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data.extend_from_slice(&SYNC_FLUSH);
+        data
+    }
+
+    #[test]
+    fn inflate_decodes_fixed_huffman_example_from_rfc7692() {
+        let mut inflater = Inflater::new();
+        let input = with_sync_flush(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+
+        assert_eq!(inflater.inflate(&input, 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn inflate_uses_earlier_output_as_history() {
+        let mut inflater = Inflater::new();
+        inflater.inflate(&with_sync_flush(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]), 1024).unwrap();
+
+        // The second "Hello" from Section 7.2.3.2 of RFC 7692 is just a
+        // back-reference to the first.
+        let output = inflater.inflate(&with_sync_flush(&[0xf2, 0x00, 0x11, 0x00, 0x00]), 1024).unwrap();
+
+        assert_eq!(output, b"Hello");
+    }
+
+    #[test]
+    fn inflate_decodes_stored_blocks() {
+        let mut inflater = Inflater::new();
+        let input = [0x01, 0x05, 0x00, 0xfa, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+
+        assert_eq!(inflater.inflate(&input, 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn inflate_decodes_dynamic_huffman_blocks() {
+        // zlib's output at level 9 for the text below, which it
+        // encodes with a dynamic block.
+        let input = [
+            0x94, 0x8a, 0x8b, 0x0d, 0x80, 0x20, 0x0c, 0x05, 0x57, 0xe9, 0x04, 0xee, 0x54, 0x01, 0x29, 0x2a,
+            0xad, 0x42, 0xfd, 0x4e, 0x2f, 0x12, 0x16, 0xe0, 0x92, 0x97, 0xdc, 0x4b, 0x4e, 0xc9, 0x81, 0x41,
+            0x85, 0x5c, 0x26, 0x0c, 0x5a, 0x6e, 0x2c, 0x8a, 0x6c, 0xab, 0x5b, 0xf1, 0x80, 0xea, 0xaa, 0x13,
+            0xea, 0x50, 0xa5, 0xa7, 0x77, 0x0d, 0xfd, 0x41, 0x44, 0x11, 0x09, 0x81, 0x39, 0x67, 0xa2, 0x94,
+            0xec, 0x6a, 0x8e, 0x78, 0x4d, 0xfe, 0xd9, 0xc6, 0x73, 0x99, 0xef, 0xfd, 0xed, 0xab, 0x3f, 0x00,
+        ];
+        let expected = "the cat sat on the mat and the dog ate the hat. ".repeat(2) +
+            &"eeeeeeeetttttaaaoooiinnsshhrrdlcumwfgypbvkjxqz".repeat(2);
+        let mut inflater = Inflater::new();
+
+        let output = inflater.inflate(&with_sync_flush(&input), 1024).unwrap();
+
+        assert_eq!(output, expected.as_bytes());
+    }
+
+    #[test]
+    fn inflate_rejects_stored_block_with_bad_length_complement() {
+        let mut inflater = Inflater::new();
+        let input = [0x01, 0x05, 0x00, 0x00, 0x00, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+
+        assert_eq!(inflater.inflate(&input, 1024), Err(InflateError::InvalidData));
+    }
+
+    #[test]
+    fn inflate_rejects_truncated_input() {
+        let mut inflater = Inflater::new();
+        assert_eq!(inflater.inflate(&[0xf2, 0x48, 0xcd], 1024), Err(InflateError::InvalidData));
+    }
+
+    #[test]
+    fn inflate_rejects_reserved_block_type() {
+        let mut inflater = Inflater::new();
+        assert_eq!(inflater.inflate(&[0x07], 1024), Err(InflateError::InvalidData));
+    }
+
+    #[test]
+    fn inflate_rejects_output_over_the_limit() {
+        let mut deflater = Deflater::new(15);
+        let compressed = deflater.deflate(&[0; 10000]);
+        let mut inflater = Inflater::new();
+
+        assert_eq!(inflater.inflate(&compressed, 1000), Err(InflateError::TooLarge));
+    }
+
+    #[test]
+    fn deflate_compresses_example_from_rfc7692() {
+        let mut deflater = Deflater::new(15);
+        let output = deflater.deflate(b"Hello");
+
+        assert_eq!(output, with_sync_flush(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]));
+    }
+
+    #[test]
+    fn deflate_refers_back_to_earlier_input() {
+        let mut deflater = Deflater::new(15);
+        let mut inflater = Inflater::new();
+        let first = deflater.deflate(b"Hello");
+        inflater.inflate(&first, 1024).unwrap();
+
+        let second = deflater.deflate(b"Hello");
+
+        assert!(second.len() < first.len());
+        assert_eq!(inflater.inflate(&second, 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn deflate_without_history_after_reset() {
+        let mut deflater = Deflater::new(15);
+        deflater.deflate(b"Hello");
+        deflater.reset();
+
+        assert_eq!(deflater.deflate(b"Hello"), with_sync_flush(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]));
+    }
+
+    #[test]
+    fn deflate_writes_only_sync_flush_for_empty_input() {
+        let mut deflater = Deflater::new(15);
+        assert_eq!(deflater.deflate(b""), with_sync_flush(&[0x00]));
+    }
+
+    #[test]
+    fn deflate_and_inflate_round_trip_repetitive_data() {
+        let input = "{\"user\":\"alice\",\"status\":\"online\"},".repeat(500);
+        let mut deflater = Deflater::new(15);
+        let mut inflater = Inflater::new();
+
+        let compressed = deflater.deflate(input.as_bytes());
+
+        assert!(compressed.len() < input.len() / 10);
+        assert_eq!(inflater.inflate(&compressed, input.len()).unwrap(), input.as_bytes());
+    }
+
+    #[test]
+    fn deflate_and_inflate_round_trip_across_calls() {
+        let mut deflater = Deflater::new(9);
+        let mut inflater = Inflater::new();
+
+        for round in 0..20 {
+            let input: Vec<u8> = (0..3000).map(|index| ((index * 7 + round) % 251) as u8).collect();
+            let compressed = deflater.deflate(&input);
+
+            assert_eq!(inflater.inflate(&compressed, input.len()).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn deflate_stores_incompressible_data() {
+        let mut state: u32 = 12345;
+        let input: Vec<u8> = (0..70000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        let mut deflater = Deflater::new(15);
+        let mut inflater = Inflater::new();
+
+        let compressed = deflater.deflate(&input);
+
+        assert!(compressed.len() <= input.len() + 20);
+        assert_eq!(inflater.inflate(&compressed, input.len()).unwrap(), input);
+    }
+}
@@ -0,0 +1,121 @@
+/// A hash function that takes its input a piece at a time.
+pub trait Digest {
+    /// The digest, such as `[u8; 32]` for SHA-256.
+    type Output: AsRef<[u8]>;
+
+    /// Bytes in a block of input, which HMAC pads its key out to.
+    const BLOCK_SIZE: usize;
+
+    /// Bytes in the digest.
+    const OUTPUT_SIZE: usize;
+
+    fn update(&mut self, input: &[u8]);
+
+    /// Returns the digest of everything passed to `update`, then resets
+    /// the context for a new message.
+    fn finalize(&mut self) -> Self::Output;
+
+    /// Discards any input, as if the context had just been created.
+    fn reset(&mut self);
+
+    /// The digest of a message that's all in memory.
+    fn hash(input: &[u8]) -> Self::Output where Self: Sized + Default {
+        let mut context = Self::default();
+        context.update(input);
+        context.finalize()
+    }
+}
+
+/// Collects input into blocks of `N` bytes for a hash function's
+/// compression function, and pads the final block with a 1 bit, zeros
+/// and the message length in bits, as the SHA family does (Section 5.1
+/// of FIPS 180-4).
+pub(crate) struct BlockBuffer<const N: usize> {
+    block: [u8; N],
+    index: usize,
+    /// Bytes of input so far.
+    length: u64,
+}
+
+impl<const N: usize> BlockBuffer<N> {
+    pub(crate) fn new() -> BlockBuffer<N> {
+        BlockBuffer { block: [0; N], index: 0, length: 0 }
+    }
+
+    /// Adds input, calling `compress` on each block as it fills.
+    pub(crate) fn update<F: FnMut(&[u8; N])>(&mut self, mut input: &[u8], mut compress: F) {
+        self.length += input.len() as u64;
+
+        while self.index + input.len() >= N {
+            let filler = N - self.index;
+            self.block[self.index..].copy_from_slice(&input[..filler]);
+            compress(&self.block);
+
+            self.index = 0;
+            input = &input[filler..];
+        }
+
+        self.block[self.index..(self.index + input.len())].copy_from_slice(input);
+        self.index += input.len();
+    }
+
+    /// Pads out and compresses the final block or two, with the length
+    /// in the last `length_size` bytes, then empties the buffer.
+    pub(crate) fn finish<F: FnMut(&[u8; N])>(&mut self, length_size: usize, mut compress: F) {
+        let length_in_bits = (self.length as u128) * 8;
+
+        self.block[self.index] = 0x80;
+        for byte in &mut self.block[(self.index + 1)..] {
+            *byte = 0;
+        }
+
+        if self.index >= N - length_size {
+            compress(&self.block);
+            self.block = [0; N];
+        }
+
+        let length_bytes = length_in_bits.to_be_bytes();
+        self.block[(N - length_size)..].copy_from_slice(&length_bytes[(16 - length_size)..]);
+        compress(&self.block);
+
+        self.reset();
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.index = 0;
+        self.length = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_buffer_compresses_each_full_block() {
+        let mut buffer: BlockBuffer<4> = BlockBuffer::new();
+        let mut blocks = Vec::new();
+
+        buffer.update(b"abc", |block| blocks.push(*block));
+        buffer.update(b"defghij", |block| blocks.push(*block));
+
+        assert_eq!(blocks, vec![*b"abcd", *b"efgh"]);
+    }
+
+    #[test]
+    fn block_buffer_adds_another_block_when_length_doesnt_fit() {
+        let mut blocks = Vec::new();
+
+        let mut buffer: BlockBuffer<8> = BlockBuffer::new();
+        buffer.update(b"ab", |block| blocks.push(*block));
+        buffer.finish(2, |block| blocks.push(*block));
+
+        assert_eq!(blocks, vec![[b'a', b'b', 0x80, 0, 0, 0, 0, 16]]);
+
+        blocks.clear();
+        buffer.update(b"abcdefg", |block| blocks.push(*block));
+        buffer.finish(2, |block| blocks.push(*block));
+
+        assert_eq!(blocks, vec![[b'a', b'b', b'c', b'd', b'e', b'f', b'g', 0x80], [0, 0, 0, 0, 0, 0, 0, 56]]);
+    }
+}
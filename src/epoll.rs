@@ -0,0 +1,228 @@
+use std::io;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+// Constants from <sys/epoll.h>. The crate has no dependencies, so the
+// syscalls are declared here rather than taken from the libc crate;
+// the C library std already links provides them.
+const EPOLL_CLOEXEC: c_int = 0o2000000;
+const EPOLL_CTL_ADD: c_int = 1;
+const EPOLL_CTL_DEL: c_int = 2;
+const EPOLL_CTL_MOD: c_int = 3;
+
+const EPOLLIN: u32 = 0x001;
+const EPOLLOUT: u32 = 0x004;
+const EPOLLERR: u32 = 0x008;
+const EPOLLHUP: u32 = 0x010;
+const EPOLLRDHUP: u32 = 0x2000;
+
+/// The kernel packs this struct on x86-64 only.
+#[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+#[derive(Clone, Copy)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern "C" {
+    fn epoll_create1(flags: c_int) -> c_int;
+    fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+    fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+/// What a registered file descriptor should be watched for.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Interest {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Interest {
+    fn events(self) -> u32 {
+        match self {
+            Interest::Read => EPOLLIN | EPOLLRDHUP,
+            Interest::Write => EPOLLOUT,
+            Interest::ReadWrite => EPOLLIN | EPOLLRDHUP | EPOLLOUT,
+        }
+    }
+}
+
+/// Readiness reported for the file descriptor registered with `token`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Event {
+    pub token: u64,
+    pub readable: bool,
+    pub writable: bool,
+    /// The peer hung up or the socket has an error. There may still be
+    /// data to read first.
+    pub closed: bool,
+}
+
+/// A level-triggered epoll instance.
+pub struct Epoll {
+    fd: RawFd,
+}
+
+impl Epoll {
+    pub fn new() -> io::Result<Epoll> {
+        let fd = check(unsafe { epoll_create1(EPOLL_CLOEXEC) })?;
+        Ok(Epoll { fd })
+    }
+
+    pub fn add(&self, fd: RawFd, token: u64, interest: Interest) -> io::Result<()> {
+        self.control(EPOLL_CTL_ADD, fd, token, interest)
+    }
+
+    pub fn modify(&self, fd: RawFd, token: u64, interest: Interest) -> io::Result<()> {
+        self.control(EPOLL_CTL_MOD, fd, token, interest)
+    }
+
+    pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+        // Kernels before 2.6.9 insist on a non-null event even though
+        // it is ignored.
+        let mut event = EpollEvent { events: 0, data: 0 };
+        check(unsafe { epoll_ctl(self.fd, EPOLL_CTL_DEL, fd, &mut event) }).map(|_| ())
+    }
+
+    fn control(&self, op: c_int, fd: RawFd, token: u64, interest: Interest) -> io::Result<()> {
+        let mut event = EpollEvent { events: interest.events(), data: token };
+        check(unsafe { epoll_ctl(self.fd, op, fd, &mut event) }).map(|_| ())
+    }
+
+    /// Waits until at least one registered file descriptor is ready or
+    /// the timeout passes, replacing the contents of `events` with what
+    /// was reported. A signal interrupting the wait is reported as no
+    /// events rather than an error.
+    pub fn wait(&self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        let mut raw = [EpollEvent { events: 0, data: 0 }; 256];
+        let count = unsafe { epoll_wait(self.fd, raw.as_mut_ptr(), raw.len() as c_int, timeout_millis(timeout)) };
+
+        events.clear();
+
+        if count < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(error),
+            };
+        }
+
+        for event in &raw[..count as usize] {
+            let flags = event.events;
+
+            events.push(Event {
+                token: event.data,
+                readable: flags & EPOLLIN != 0,
+                writable: flags & EPOLLOUT != 0,
+                closed: flags & (EPOLLHUP | EPOLLRDHUP | EPOLLERR) != 0,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+fn timeout_millis(timeout: Option<Duration>) -> c_int {
+    match timeout {
+        Some(timeout) => {
+            // Round up so a short timeout doesn't turn into a busy loop.
+            let millis = timeout.as_nanos().div_ceil(1_000_000);
+            if millis > c_int::MAX as u128 { c_int::MAX } else { millis as c_int }
+        },
+        None => -1,
+    }
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        (server, client)
+    }
+
+    #[test]
+    fn wait_reports_readable_socket_with_its_token() {
+        let (server, mut client) = connect();
+        let epoll = Epoll::new().unwrap();
+        let mut events = Vec::new();
+
+        epoll.add(server.as_raw_fd(), 7, Interest::Read).unwrap();
+        epoll.wait(&mut events, Some(Duration::from_millis(10))).unwrap();
+        assert!(events.is_empty());
+
+        client.write_all(b"hello").unwrap();
+        epoll.wait(&mut events, Some(Duration::from_secs(5))).unwrap();
+
+        assert_eq!(events, vec![Event { token: 7, readable: true, writable: false, closed: false }]);
+    }
+
+    #[test]
+    fn wait_reports_writable_socket_once_interest_is_modified() {
+        let (server, _client) = connect();
+        let epoll = Epoll::new().unwrap();
+        let mut events = Vec::new();
+
+        epoll.add(server.as_raw_fd(), 1, Interest::Read).unwrap();
+        epoll.modify(server.as_raw_fd(), 1, Interest::ReadWrite).unwrap();
+        epoll.wait(&mut events, Some(Duration::from_secs(5))).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].writable);
+    }
+
+    #[test]
+    fn wait_reports_peer_hanging_up() {
+        let (server, client) = connect();
+        let epoll = Epoll::new().unwrap();
+        let mut events = Vec::new();
+
+        epoll.add(server.as_raw_fd(), 1, Interest::Read).unwrap();
+        drop(client);
+        epoll.wait(&mut events, Some(Duration::from_secs(5))).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].closed);
+    }
+
+    #[test]
+    fn delete_stops_reporting_socket() {
+        let (server, mut client) = connect();
+        let epoll = Epoll::new().unwrap();
+        let mut events = Vec::new();
+
+        epoll.add(server.as_raw_fd(), 1, Interest::Read).unwrap();
+        epoll.delete(server.as_raw_fd()).unwrap();
+        client.write_all(b"hello").unwrap();
+        epoll.wait(&mut events, Some(Duration::from_millis(10))).unwrap();
+
+        assert!(events.is_empty());
+    }
+}
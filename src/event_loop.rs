@@ -0,0 +1,563 @@
+//! A single-threaded server core that watches every connection with
+//! epoll instead of giving each one a thread. Sockets are
+//! non-blocking, and each connection is a state machine fed with
+//! whatever bytes have arrived: plain HTTP connections collect a
+//! request, queue the response and flush it as the client makes room,
+//! while upgraded connections are read frame by frame.
+//!
+//! Handlers run on the loop's thread, so a slow one holds up every
+//! other connection. Frames sent to a WebSocket that the client hasn't
+//! made room for are queued and written as it does, so a slow reader
+//! only holds up itself.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use strudel::epoll::{Epoll, Event, Interest};
+use strudel::request::{self, BodyLength, ChunkedDecoder, HTTPError, Method};
+use strudel::response::BodyReader;
+use strudel::websocket::{self, Connection, FrameError, Handler, Handshake};
+
+use {log_request, respond, send, websocket_handshake, with_connection_header, write_error, App, IDLE_TIMEOUT, REQUEST_TIMEOUT};
+
+/// The token the listening socket is registered with. Connections are
+/// numbered from one.
+const LISTENER: u64 = 0;
+
+/// How often connections are checked for having timed out.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// How much of a streamed body is read ahead of what the client has
+/// taken.
+const BODY_CHUNK_SIZE: usize = 64 * 1024;
+
+enum State {
+    Http(HttpState),
+    WebSocket(Connection, Arc<dyn Handler>),
+    /// A WebSocket that has ended but still has frames queued, such as
+    /// its Close frame.
+    Draining(Connection),
+}
+
+struct HttpState {
+    stream: TcpStream,
+    input: Vec<u8>,
+    /// Where decoding a chunked body that is still arriving has got
+    /// to, so it doesn't start again from the top after every read.
+    chunked: Option<ChunkedDecoder>,
+    output: Vec<u8>,
+    /// The rest of a response body that is read into the output as it
+    /// drains, so large files aren't held in memory. Requests behind it
+    /// wait until it has all gone out.
+    body: Option<BodyReader>,
+    /// When the connection gives up waiting: `IDLE_TIMEOUT` after the
+    /// last response while there is no input, and `REQUEST_TIMEOUT`
+    /// after a request starts to arrive.
+    deadline: Instant,
+    /// Close the connection once the output has been written.
+    closing: bool,
+    /// The client has shut down its side, so no more input is coming.
+    peer_closed: bool,
+    /// Switch to the WebSocket protocol once the 101 response has been
+    /// written.
+    upgrade: Option<(Handshake, Arc<dyn Handler>)>,
+}
+
+struct Client {
+    fd: RawFd,
+    state: State,
+}
+
+/// Serves connections from the listener until the server shuts down
+/// and every connection has finished, or an error stops the loop.
+pub fn run(listener: TcpListener, app: Arc<App>) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    let epoll = Arc::new(Epoll::new()?);
+    epoll.add(listener.as_raw_fd(), LISTENER, Interest::Read)?;
+    let mut listener = Some(listener);
+
+    let mut clients: HashMap<u64, Client> = HashMap::new();
+    let mut next_token = LISTENER + 1;
+    let mut events = Vec::new();
+
+    loop {
+        epoll.wait(&mut events, Some(SWEEP_INTERVAL))?;
+
+        for event in &events {
+            if event.token == LISTENER {
+                if let Some(ref listener) = listener {
+                    accept(listener, &epoll, &mut clients, &mut next_token);
+                }
+                continue;
+            }
+
+            if let Some(client) = clients.remove(&event.token) {
+                if let Some(client) = handle_event(client, event, event.token, &epoll, &app) {
+                    clients.insert(event.token, client);
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let expired: Vec<u64> = clients.iter()
+            .filter(|&(_, client)| match client.state {
+                State::Http(ref http) => http.deadline <= now,
+                State::WebSocket(ref connection, _) | State::Draining(ref connection) => connection.is_stalled(),
+            })
+            .map(|(token, _)| *token)
+            .collect();
+
+        for token in expired {
+            if let Some(client) = clients.remove(&token) {
+                time_out(client, &epoll, &app);
+            }
+        }
+
+        if app.is_shutting_down() {
+            if let Some(listener) = listener.take() {
+                let _ = epoll.delete(listener.as_raw_fd());
+            }
+
+            // Connections waiting for their next request won't get
+            // one. The rest close once their response has gone out.
+            let idle: Vec<u64> = clients.iter()
+                .filter(|&(_, client)| is_idle(client))
+                .map(|(token, _)| *token)
+                .collect();
+
+            for token in idle {
+                if let Some(client) = clients.remove(&token) {
+                    let _ = epoll.delete(client.fd);
+                }
+            }
+
+            if clients.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn is_idle(client: &Client) -> bool {
+    match client.state {
+        State::Http(ref http) => http.input.is_empty() && http.output.is_empty() && http.body.is_none() && http.upgrade.is_none(),
+        State::WebSocket(..) | State::Draining(_) => false,
+    }
+}
+
+fn accept(listener: &TcpListener, epoll: &Epoll, clients: &mut HashMap<u64, Client>, next_token: &mut u64) {
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return,
+            Err(error) => {
+                println!("error accepting connection: {}", error);
+                return;
+            },
+        };
+
+        if stream.set_nonblocking(true).is_err() {
+            continue;
+        }
+
+        let token = *next_token;
+        *next_token += 1;
+
+        let fd = stream.as_raw_fd();
+        if epoll.add(fd, token, Interest::Read).is_err() {
+            continue;
+        }
+
+        let http = HttpState {
+            stream,
+            input: Vec::new(),
+            chunked: None,
+            output: Vec::new(),
+            body: None,
+            deadline: Instant::now() + IDLE_TIMEOUT,
+            closing: false,
+            peer_closed: false,
+            upgrade: None,
+        };
+
+        clients.insert(token, Client { fd, state: State::Http(http) });
+    }
+}
+
+/// Moves the connection along in response to an event. Returns the
+/// client if the connection is still open.
+fn handle_event(client: Client, event: &Event, token: u64, epoll: &Arc<Epoll>, app: &Arc<App>) -> Option<Client> {
+    let Client { fd, state } = client;
+
+    let state = match state {
+        State::Http(mut http) => {
+            if (event.readable || event.closed) && !read_available(&mut http) {
+                let _ = epoll.delete(fd);
+                return None;
+            }
+
+            advance(http, fd, token, epoll, app)
+        },
+        State::WebSocket(mut connection, handler) => {
+            let result = if event.writable { connection.flush() } else { Ok(()) };
+            let result = result.and_then(|_| connection.read_more());
+
+            if receive_messages(&mut connection, handler.as_ref(), result, app) {
+                Some(State::WebSocket(connection, handler))
+            } else {
+                drain(connection, fd, epoll)
+            }
+        },
+        State::Draining(mut connection) => {
+            let mut open = connection.flush().is_ok() && connection.read_more().is_ok();
+
+            // Whatever the client sends in the meantime is ignored.
+            while open {
+                match connection.next_message() {
+                    Ok(Some(_)) => {},
+                    Ok(None) => break,
+                    Err(_) => open = false,
+                }
+            }
+
+            if open {
+                drain(connection, fd, epoll)
+            } else {
+                let _ = epoll.delete(fd);
+                None
+            }
+        },
+    };
+
+    state.map(|state| Client { fd, state })
+}
+
+/// Reads everything the client has sent so far. Returns false if the
+/// connection failed.
+fn read_available(http: &mut HttpState) -> bool {
+    let mut chunk = [0; READ_CHUNK_SIZE];
+    let was_empty = http.input.is_empty();
+
+    loop {
+        match http.stream.read(&mut chunk) {
+            Ok(0) => {
+                http.peer_closed = true;
+                break;
+            },
+            Ok(count) => http.input.extend_from_slice(&chunk[..count]),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+            Err(_) => return false,
+        }
+    }
+
+    if was_empty && !http.input.is_empty() {
+        http.deadline = Instant::now() + REQUEST_TIMEOUT;
+    }
+
+    true
+}
+
+/// Answers every complete request in the input, in order. Stops at
+/// an upgrade or an error, since nothing after those is HTTP.
+fn process_requests(http: &mut HttpState, app: &Arc<App>) {
+    while !http.closing && http.upgrade.is_none() && http.body.is_none() {
+        match next_request(http, app) {
+            Ok(true) => {},
+            Ok(false) => return,
+            Err(error) => {
+                write_error(error, &mut http.output);
+                http.closing = true;
+            },
+        }
+    }
+}
+
+/// Answers the request at the front of the input if all of it has
+/// arrived. Returns false if it is still incomplete.
+fn next_request(http: &mut HttpState, app: &Arc<App>) -> Result<bool, HTTPError> {
+    let header_length = match request::head_length(&http.input)? {
+        Some(length) => length,
+        None => return Ok(false),
+    };
+
+    let mut request = request::parse_request(&http.input[..header_length])?;
+    let rest = &http.input[header_length..];
+
+    let body_length = match request.body_length()? {
+        BodyLength::Empty => 0,
+        BodyLength::Fixed(length) if length > app.max_body_size => return Err(HTTPError::PayloadTooLarge),
+        BodyLength::Fixed(length) if rest.len() < length => return Ok(false),
+        BodyLength::Fixed(length) => {
+            request.body = rest[..length].to_vec();
+            length
+        },
+        BodyLength::Chunked => match http.chunked.get_or_insert_with(|| ChunkedDecoder::new(app.max_body_size)).decode(rest)? {
+            Some((body, length)) => {
+                request.body = body;
+                length
+            },
+            None => return Ok(false),
+        },
+    };
+
+    log_request(&request);
+
+    let keep_alive = app.keep_alive(&request);
+
+    if request.is_websocket() {
+        let handler = match app.handlers.get(request.path()) {
+            Some(handler) => handler.clone(),
+            None => return Err(HTTPError::NotFound),
+        };
+
+        if !app.reserve_websocket() {
+            return Err(HTTPError::ServiceUnavailable);
+        }
+
+        match websocket_handshake(&request, handler.as_ref(), app) {
+            Ok((response, handshake)) => {
+                let _ = send(response, false, true, &mut http.output);
+                http.upgrade = Some((handshake, handler));
+            },
+            Err(error) => {
+                app.release_websocket();
+                return Err(error);
+            },
+        }
+    } else {
+        let response = with_connection_header(respond(&mut request, app), keep_alive);
+
+        match response.into_parts(request.method == Method::Head) {
+            Ok((head, body)) => {
+                http.output.extend_from_slice(&head);
+                http.body = body;
+                http.closing = !keep_alive;
+            },
+            Err(_) => return Err(HTTPError::InternalServerError),
+        }
+    }
+
+    http.input.drain(..(header_length + body_length));
+    http.chunked = None;
+    http.deadline = Instant::now() + if http.input.is_empty() { IDLE_TIMEOUT } else { REQUEST_TIMEOUT };
+
+    Ok(true)
+}
+
+/// Answers whatever requests have arrived and writes as much of the
+/// output as the client will take, then closes or upgrades the
+/// connection once it has all gone out.
+fn advance(mut http: HttpState, fd: RawFd, token: u64, epoll: &Arc<Epoll>, app: &Arc<App>) -> Option<State> {
+    loop {
+        process_requests(&mut http, app);
+
+        if !flush(&mut http) {
+            return abandon(http, fd, epoll, app);
+        }
+
+        if !http.output.is_empty() {
+            // A client that has shut down its side would otherwise be
+            // reported as readable over and over.
+            let interest = if http.peer_closed { Interest::Write } else { Interest::ReadWrite };
+            let _ = epoll.modify(fd, token, interest);
+            return Some(State::Http(http));
+        }
+
+        // Everything queued has gone out. Requests held up behind a
+        // streamed body can be answered now, and the rest of those
+        // that arrived in full before the client shut down its side.
+        if http.closing || http.upgrade.is_some() || !has_request(&http) {
+            break;
+        }
+    }
+
+    if http.peer_closed {
+        http.closing = true;
+    }
+
+    let _ = epoll.modify(fd, token, Interest::Read);
+
+    if http.closing {
+        return abandon(http, fd, epoll, app);
+    }
+
+    let (mut handshake, handler) = match http.upgrade.take() {
+        Some(upgrade) => upgrade,
+        None => return Some(State::Http(http)),
+    };
+
+    handshake.buffered = mem::take(&mut http.input);
+
+    let mut connection = match Connection::new(http.stream, handshake) {
+        Ok(connection) => connection,
+        Err(_) => {
+            app.release_websocket();
+            let _ = epoll.delete(fd);
+            return None;
+        },
+    };
+
+    // Frames the client isn't ready for wait for the socket to become
+    // writable, rather than holding up the loop.
+    let watcher = epoll.clone();
+    connection.queue_writes(Box::new(move |waiting| {
+        let _ = watcher.modify(fd, token, if waiting { Interest::ReadWrite } else { Interest::Read });
+    }));
+
+    let socket = connection.socket();
+    app.hub.add(&socket);
+    handler.on_open(&socket);
+
+    // Frames that arrived along with the handshake won't trigger
+    // another event, so they are handled straight away.
+    if receive_messages(&mut connection, handler.as_ref(), Ok(()), app) {
+        Some(State::WebSocket(connection, handler))
+    } else {
+        drain(connection, fd, epoll)
+    }
+}
+
+/// Keeps an ended WebSocket around until its queued frames have gone
+/// out.
+fn drain(connection: Connection, fd: RawFd, epoll: &Epoll) -> Option<State> {
+    if connection.has_queued_output() {
+        Some(State::Draining(connection))
+    } else {
+        let _ = epoll.delete(fd);
+        None
+    }
+}
+
+/// Whether a whole request head is waiting to be answered.
+fn has_request(http: &HttpState) -> bool {
+    match request::head_length(&http.input) {
+        Ok(length) => length.is_some(),
+        Err(_) => true,
+    }
+}
+
+/// Returns false if the client can't be written to, or the body being
+/// streamed can't be read.
+fn flush(http: &mut HttpState) -> bool {
+    loop {
+        if http.output.is_empty() && !fill_from_body(http) {
+            return false;
+        }
+
+        if http.output.is_empty() {
+            return true;
+        }
+
+        match http.stream.write(&http.output) {
+            Ok(0) => return false,
+            Ok(count) => {
+                http.output.drain(..count);
+
+                // A client that keeps taking a long response hasn't
+                // timed out. Once it has all of it, the connection
+                // waits for the next request as usual.
+                let finished = http.output.is_empty() && http.body.is_none() && http.input.is_empty();
+                http.deadline = Instant::now() + if finished { IDLE_TIMEOUT } else { REQUEST_TIMEOUT };
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Reads the next piece of the body being streamed into the output.
+/// Returns false if the body couldn't be read, in which case the
+/// response can't be finished.
+fn fill_from_body(http: &mut HttpState) -> bool {
+    let mut body = match http.body.take() {
+        Some(body) => body,
+        None => return true,
+    };
+
+    let mut chunk = vec![0; BODY_CHUNK_SIZE];
+
+    loop {
+        match body.read(&mut chunk) {
+            Ok(0) => return true,
+            Ok(count) => {
+                http.output.extend_from_slice(&chunk[..count]);
+                http.body = Some(body);
+                return true;
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+            Err(_) => return false,
+        }
+    }
+}
+
+fn abandon(http: HttpState, fd: RawFd, epoll: &Epoll, app: &Arc<App>) -> Option<State> {
+    if http.upgrade.is_some() {
+        app.release_websocket();
+    }
+
+    let _ = epoll.delete(fd);
+    None
+}
+
+/// Handles every message already in the connection's buffer, then the
+/// result of the read that filled it. Returns false once the
+/// connection is over.
+fn receive_messages(connection: &mut Connection, handler: &dyn Handler, mut read: Result<(), FrameError>, app: &Arc<App>) -> bool {
+    loop {
+        let result = match connection.next_message() {
+            Ok(Some(message)) => Ok(message),
+            Ok(None) => match mem::replace(&mut read, Ok(())) {
+                Ok(()) => return true,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+
+        if let Some((code, reason)) = websocket::dispatch(connection, handler, result) {
+            close_websocket(connection, handler, code, &reason, app);
+            return false;
+        }
+    }
+}
+
+fn close_websocket(connection: &Connection, handler: &dyn Handler, code: Option<u16>, reason: &str, app: &Arc<App>) {
+    let socket = connection.socket();
+    app.hub.remove(&socket);
+    handler.on_close(&socket, code, reason);
+    app.release_websocket();
+}
+
+/// Drops a connection whose deadline has passed, telling the client
+/// why if it was partway through sending a request. A WebSocket whose
+/// client has stopped taking what is sent to it is dropped as if the
+/// connection had failed.
+fn time_out(client: Client, epoll: &Epoll, app: &Arc<App>) {
+    match client.state {
+        State::Http(mut http) => {
+            if !http.input.is_empty() && http.output.is_empty() {
+                let mut response = Vec::new();
+                write_error(HTTPError::RequestTimeout, &mut response);
+                let _ = http.stream.write(&response);
+            }
+        },
+        State::WebSocket(mut connection, handler) => {
+            handler.on_error(&connection.socket(), &FrameError::ConnectionClosed);
+            connection.fail(&FrameError::ConnectionClosed);
+            close_websocket(&connection, handler.as_ref(), None, "", app);
+        },
+        State::Draining(_) => {},
+    }
+
+    let _ = epoll.delete(client.fd);
+}
@@ -0,0 +1,239 @@
+use digest::Digest;
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// A keyed message authentication code from RFC 2104, over any of the
+/// crate's hash contexts, as in `Hmac::<SHA256Context>::new(key)`. It
+/// takes its message a piece at a time, like the hash underneath.
+pub struct Hmac<D: Digest + Default> {
+    inner: D,
+    /// The key padded out to a block, XORed with ipad and opad.
+    inner_key: Vec<u8>,
+    outer_key: Vec<u8>,
+}
+
+impl<D: Digest + Default> Hmac<D> {
+    /// Keys longer than the hash's block are hashed first, as Section 2
+    /// of RFC 2104 requires. Keys of any length are accepted, but one
+    /// shorter than the digest weakens the code.
+    pub fn new(key: &[u8]) -> Hmac<D> {
+        let mut block = if key.len() > D::BLOCK_SIZE {
+            D::hash(key).as_ref().to_vec()
+        } else {
+            key.to_vec()
+        };
+        block.resize(D::BLOCK_SIZE, 0);
+
+        let inner_key: Vec<u8> = block.iter().map(|byte| byte ^ IPAD).collect();
+        let outer_key: Vec<u8> = block.iter().map(|byte| byte ^ OPAD).collect();
+
+        let mut inner = D::default();
+        inner.update(&inner_key);
+
+        Hmac { inner, inner_key, outer_key }
+    }
+
+    /// Checks a tag received with the message, such as a webhook's
+    /// signature, against the one computed for it. The comparison takes
+    /// the same time however much of the tag is right. Resets the
+    /// context, as `finalize` does.
+    pub fn verify(&mut self, tag: &[u8]) -> bool {
+        constant_time_eq(self.finalize().as_ref(), tag)
+    }
+}
+
+impl<D: Digest + Default> Digest for Hmac<D> {
+    type Output = D::Output;
+
+    const BLOCK_SIZE: usize = D::BLOCK_SIZE;
+    const OUTPUT_SIZE: usize = D::OUTPUT_SIZE;
+
+    fn update(&mut self, input: &[u8]) {
+        self.inner.update(input);
+    }
+
+    /// Returns the tag for the message so far, then starts a new
+    /// message under the same key.
+    fn finalize(&mut self) -> D::Output {
+        let inner_digest = self.inner.finalize();
+        self.inner.update(&self.inner_key);
+
+        let mut outer = D::default();
+        outer.update(&self.outer_key);
+        outer.update(inner_digest.as_ref());
+        outer.finalize()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.inner.update(&self.inner_key);
+    }
+}
+
+/// The HMAC of a message that's all in memory.
+pub fn hmac<D: Digest + Default>(key: &[u8], message: &[u8]) -> D::Output {
+    let mut context = Hmac::<D>::new(key);
+    context.update(message);
+    context.finalize()
+}
+
+/// Compares two byte strings without stopping at the first difference,
+/// so the time taken doesn't reveal how much of a guessed tag is right.
+/// The lengths aren't secret, so those are compared first.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let difference = a.iter().zip(b).fold(0u8, |difference, (x, y)| difference | (x ^ y));
+    difference == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1::SHA1Context;
+    use sha256::SHA256Context;
+    use sha512::SHA512Context;
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// The message of test case 7 in RFC 4231.
+    const RFC4231_CASE_7: &[u8] = b"This is a test using a larger than block-size key and a larger than block-size data. \
+                                    The key needs to be hashed before being used by the HMAC algorithm.";
+
+    /// The keys and messages of test cases 1 to 4 in RFC 2202 and
+    /// RFC 4231, which the two share.
+    fn common_cases() -> Vec<(Vec<u8>, Vec<u8>)> {
+        vec![
+            (vec![0x0b; 20], b"Hi There".to_vec()),
+            (b"Jefe".to_vec(), b"what do ya want for nothing?".to_vec()),
+            (vec![0xaa; 20], vec![0xdd; 50]),
+            ((1..26).collect(), vec![0xcd; 50]),
+        ]
+    }
+
+    #[test]
+    fn hmac_sha1_passes_test_cases_from_rfc2202() {
+        let expected = [
+            "b617318655057264e28bc0b6fb378c8ef146be00",
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+            "125d7342b9ac11cd91a39af48aa17b4f63f175d3",
+            "4c9007f4026250c6bc8414f9bf50c86c2d7235da",
+        ];
+
+        for ((key, message), expected) in common_cases().iter().zip(&expected) {
+            assert_eq!(hex(&hmac::<SHA1Context>(key, message)), *expected);
+        }
+
+        // Test case 5 checks a tag truncated to 96 bits.
+        let tag = hmac::<SHA1Context>(&[0x0c; 20], b"Test With Truncation");
+        assert_eq!(hex(&tag[..12]), "4c1a03424b55e07fe7f27be1");
+    }
+
+    #[test]
+    fn hmac_sha1_hashes_keys_longer_than_a_block() {
+        let key = [0xaa; 80];
+
+        assert_eq!(hex(&hmac::<SHA1Context>(&key, b"Test Using Larger Than Block-Size Key - Hash Key First")),
+                   "aa4ae5e15272d00e95705637ce8a3b55ed402112");
+        assert_eq!(hex(&hmac::<SHA1Context>(&key, b"Test Using Larger Than Block-Size Key and Larger Than One Block-Size Data")),
+                   "e8e99d0f45237d786d6bbaa7965c7808bbff1a91");
+    }
+
+    #[test]
+    fn hmac_sha256_passes_test_cases_from_rfc4231() {
+        let expected = [
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+        ];
+
+        for ((key, message), expected) in common_cases().iter().zip(&expected) {
+            assert_eq!(hex(&hmac::<SHA256Context>(key, message)), *expected);
+        }
+
+        let tag = hmac::<SHA256Context>(&[0x0c; 20], b"Test With Truncation");
+        assert_eq!(hex(&tag[..16]), "a3b6167473100ee06e0c796c2955552b");
+
+        let key = [0xaa; 131];
+        assert_eq!(hex(&hmac::<SHA256Context>(&key, b"Test Using Larger Than Block-Size Key - Hash Key First")),
+                   "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+        assert_eq!(hex(&hmac::<SHA256Context>(&key, RFC4231_CASE_7)),
+                   "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2");
+    }
+
+    #[test]
+    fn hmac_sha512_passes_test_cases_from_rfc4231() {
+        let expected = [
+            "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde\
+             daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            "fa73b0089d56a284efb0f0756c890be9b1b5dbdd8ee81a3655f83e33b2279d39\
+             bf3e848279a722c806b485a47e67c807b946a337bee8942674278859e13292fb",
+            "b0ba465637458c6990e5a8c5f61d4af7e576d97ff94b872de76f8050361ee3db\
+             a91ca5c11aa25eb4d679275cc5788063a5f19741120c4f2de2adebeb10a298dd",
+        ];
+
+        for ((key, message), expected) in common_cases().iter().zip(&expected) {
+            assert_eq!(hex(&hmac::<SHA512Context>(key, message)), *expected);
+        }
+
+        let tag = hmac::<SHA512Context>(&[0x0c; 20], b"Test With Truncation");
+        assert_eq!(hex(&tag[..16]), "415fad6271580a531d4179bc891d87a6");
+
+        let key = [0xaa; 131];
+        assert_eq!(hex(&hmac::<SHA512Context>(&key, b"Test Using Larger Than Block-Size Key - Hash Key First")),
+                   "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352\
+                    6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598");
+        assert_eq!(hex(&hmac::<SHA512Context>(&key, RFC4231_CASE_7)),
+                   "e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944\
+                    b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58");
+    }
+
+    #[test]
+    fn hmac_accepts_message_in_pieces_and_resets_after_finalize() {
+        let mut context = Hmac::<SHA256Context>::new(b"Jefe");
+        context.update(b"what do ya want ");
+        context.update(b"for nothing?");
+        let first = context.finalize();
+
+        context.update(b"what do ya want for nothing?");
+        assert_eq!(context.finalize(), first);
+
+        context.update(b"discarded");
+        context.reset();
+        context.update(b"what do ya want for nothing?");
+        assert_eq!(context.finalize(), first);
+    }
+
+    #[test]
+    fn verify_checks_the_whole_tag() {
+        let tag = hmac::<SHA256Context>(b"secret", b"payload");
+        let mut context = Hmac::<SHA256Context>::new(b"secret");
+
+        context.update(b"payload");
+        assert!(context.verify(&tag));
+
+        let mut wrong = tag;
+        wrong[31] ^= 1;
+        context.update(b"payload");
+        assert!(!context.verify(&wrong));
+
+        context.update(b"payload");
+        assert!(!context.verify(&tag[..16]));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes_and_lengths() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use websocket::{Message, Socket};
+
+/// Keeps track of every open WebSocket connection and the rooms they
+/// have joined, so a message from one client can be sent on to many
+/// others. A connection is added when it opens and removed when it
+/// closes or a write to it fails.
+pub struct Hub {
+    state: Mutex<State>,
+}
+
+struct State {
+    sockets: HashMap<usize, Socket>,
+    rooms: HashMap<String, HashSet<usize>>,
+}
+
+impl State {
+    fn remove(&mut self, id: usize) {
+        self.sockets.remove(&id);
+
+        for members in self.rooms.values_mut() {
+            members.remove(&id);
+        }
+
+        self.rooms.retain(|_, members| !members.is_empty());
+    }
+}
+
+impl Default for Hub {
+    fn default() -> Hub {
+        Hub::new()
+    }
+}
+
+impl Hub {
+    pub fn new() -> Hub {
+        Hub { state: Mutex::new(State { sockets: HashMap::new(), rooms: HashMap::new() }) }
+    }
+
+    pub fn add(&self, socket: &Socket) {
+        let mut state = self.state.lock().unwrap();
+        state.sockets.insert(socket.id(), socket.clone());
+    }
+
+    /// Forgets the connection and takes it out of every room.
+    pub fn remove(&self, socket: &Socket) {
+        self.state.lock().unwrap().remove(socket.id());
+    }
+
+    /// Adds the connection to the room, creating the room if this is
+    /// its first member. Connections that aren't in the hub can't join.
+    pub fn join(&self, socket: &Socket, room: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.sockets.contains_key(&socket.id()) {
+            state.rooms.entry(room.to_string()).or_default().insert(socket.id());
+        }
+    }
+
+    /// Removes the connection from the room. Empty rooms are dropped.
+    pub fn leave(&self, socket: &Socket, room: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        let is_empty = match state.rooms.get_mut(room) {
+            Some(members) => {
+                members.remove(&socket.id());
+                members.is_empty()
+            },
+            None => false,
+        };
+
+        if is_empty {
+            state.rooms.remove(room);
+        }
+    }
+
+    /// The number of connections currently in the room.
+    pub fn room_size(&self, room: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.rooms.get(room).map_or(0, |members| members.len())
+    }
+
+    /// The number of open connections.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().sockets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sends the message to every open connection.
+    pub fn broadcast(&self, message: &Message) {
+        let sockets: Vec<Socket> = {
+            let state = self.state.lock().unwrap();
+            state.sockets.values().cloned().collect()
+        };
+
+        self.send_all(sockets, message);
+    }
+
+    /// Sends the message to every connection in the room.
+    pub fn broadcast_to(&self, room: &str, message: &Message) {
+        let sockets: Vec<Socket> = {
+            let state = self.state.lock().unwrap();
+
+            match state.rooms.get(room) {
+                Some(members) => members.iter().filter_map(|id| state.sockets.get(id)).cloned().collect(),
+                None => Vec::new(),
+            }
+        };
+
+        self.send_all(sockets, message);
+    }
+
+    /// Starts the closing handshake with every open connection, such as
+    /// with `CLOSE_GOING_AWAY` when the server is shutting down. Each
+    /// connection leaves the hub once its client replies.
+    pub fn close_all(&self, code: u16, reason: &str) {
+        let sockets: Vec<Socket> = {
+            let state = self.state.lock().unwrap();
+            state.sockets.values().cloned().collect()
+        };
+
+        for socket in sockets {
+            socket.close(Some(code), reason);
+        }
+    }
+
+    /// Writes happen without holding the lock, so one slow client
+    /// doesn't hold up joins and leaves for everybody else. Any
+    /// connection that can't be written to is shut down and removed.
+    fn send_all(&self, sockets: Vec<Socket>, message: &Message) {
+        let dead: Vec<Socket> = sockets.into_iter()
+            .filter(|socket| socket.send(message).is_err())
+            .collect();
+
+        if dead.is_empty() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        for socket in dead {
+            socket.shutdown();
+            state.remove(socket.id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+    use websocket::{parse_close_payload, parse_frame, Connection, Handshake, Opcode, CLOSE_GOING_AWAY};
+
+    /// Returns the server's socket for a new loopback connection along
+    /// with the client's end of it.
+    fn connect() -> (Socket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        (Connection::new(server, Handshake::default()).unwrap().socket(), client)
+    }
+
+    fn read_text(client: &mut TcpStream) -> String {
+        let mut buffer = [0; 64];
+        let count = client.read(&mut buffer).unwrap();
+        let (frame, _) = parse_frame(&buffer[..count]).unwrap().unwrap();
+
+        assert_eq!(frame.opcode, Opcode::Text);
+        String::from_utf8(frame.payload).unwrap()
+    }
+
+    #[test]
+    fn broadcast_sends_to_every_connection() {
+        let hub = Hub::new();
+        let (first, mut first_client) = connect();
+        let (second, mut second_client) = connect();
+        hub.add(&first);
+        hub.add(&second);
+
+        hub.broadcast(&Message::Text("hello".to_string()));
+
+        assert_eq!(read_text(&mut first_client), "hello");
+        assert_eq!(read_text(&mut second_client), "hello");
+    }
+
+    #[test]
+    fn broadcast_to_only_sends_to_room_members() {
+        let hub = Hub::new();
+        let (member, mut member_client) = connect();
+        let (outsider, mut outsider_client) = connect();
+        hub.add(&member);
+        hub.add(&outsider);
+        hub.join(&member, "lobby");
+
+        hub.broadcast_to("lobby", &Message::Text("members only".to_string()));
+        hub.broadcast(&Message::Text("everyone".to_string()));
+
+        assert_eq!(read_text(&mut member_client), "members only");
+        assert_eq!(read_text(&mut outsider_client), "everyone");
+    }
+
+    #[test]
+    fn leave_removes_connection_from_room_and_drops_empty_rooms() {
+        let hub = Hub::new();
+        let (socket, _client) = connect();
+        hub.add(&socket);
+        hub.join(&socket, "lobby");
+        assert_eq!(hub.room_size("lobby"), 1);
+
+        hub.leave(&socket, "lobby");
+
+        assert_eq!(hub.room_size("lobby"), 0);
+        assert!(hub.state.lock().unwrap().rooms.is_empty());
+    }
+
+    #[test]
+    fn join_ignores_connections_not_in_the_hub() {
+        let hub = Hub::new();
+        let (socket, _client) = connect();
+
+        hub.join(&socket, "lobby");
+
+        assert_eq!(hub.room_size("lobby"), 0);
+    }
+
+    #[test]
+    fn remove_takes_connection_out_of_every_room() {
+        let hub = Hub::new();
+        let (socket, _client) = connect();
+        hub.add(&socket);
+        hub.join(&socket, "first");
+        hub.join(&socket, "second");
+
+        hub.remove(&socket);
+
+        assert!(hub.is_empty());
+        assert_eq!(hub.room_size("first"), 0);
+        assert_eq!(hub.room_size("second"), 0);
+    }
+
+    #[test]
+    fn close_all_sends_close_frame_to_every_connection() {
+        let hub = Hub::new();
+        let (first, mut first_client) = connect();
+        let (second, mut second_client) = connect();
+        hub.add(&first);
+        hub.add(&second);
+
+        hub.close_all(CLOSE_GOING_AWAY, "Server shutting down");
+
+        for client in &mut [&mut first_client, &mut second_client] {
+            let mut buffer = [0; 64];
+            let count = client.read(&mut buffer).unwrap();
+            let (frame, _) = parse_frame(&buffer[..count]).unwrap().unwrap();
+
+            assert_eq!(frame.opcode, Opcode::Close);
+            assert_eq!(parse_close_payload(&frame.payload), Ok(Some((CLOSE_GOING_AWAY, "Server shutting down"))));
+        }
+    }
+
+    #[test]
+    fn broadcast_removes_connections_that_cant_be_written_to() {
+        let hub = Hub::new();
+        let (socket, _client) = connect();
+        hub.add(&socket);
+        hub.join(&socket, "lobby");
+        socket.close(None, "");
+
+        hub.broadcast(&Message::Text("hello".to_string()));
+
+        assert!(hub.is_empty());
+        assert_eq!(hub.room_size("lobby"), 0);
+    }
+}
@@ -0,0 +1,23 @@
+pub mod base64;
+pub mod conditional;
+pub mod date;
+pub mod deflate;
+pub mod digest;
+#[cfg(target_os = "linux")]
+pub mod epoll;
+pub mod hmac;
+pub mod hub;
+pub mod permessage_deflate;
+pub mod pool;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod sha1;
+pub mod sha256;
+pub mod sha512;
+#[cfg(unix)]
+pub mod signal;
+pub mod static_files;
+pub mod url;
+pub mod utf8;
+pub mod websocket;
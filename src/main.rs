@@ -1,103 +1,455 @@
-mod base64;
-mod request;
-mod sha1;
+extern crate strudel;
 
+#[cfg(target_os = "linux")]
+mod event_loop;
+
+use strudel::{base64, conditional, request, sha1, websocket};
+#[cfg(unix)]
+use strudel::signal;
+use strudel::hub::Hub;
+use strudel::pool::WorkerPool;
+use strudel::request::Method;
+use strudel::date::format_http_date;
+use strudel::response::{Response, Status};
+use strudel::router::{Match, Router};
+use strudel::static_files::StaticFiles;
+use strudel::permessage_deflate::DeflateParams;
+
+use std::io;
 use std::io::prelude::*;
 use std::env;
+use std::process;
 use std::collections::HashMap;
 use std::net::{TcpListener, TcpStream};
 use std::str;
-use std::fs::File;
+use std::fs::{self, File};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 const DEFAULT_PORT: u16 = 4485;
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+const DEFAULT_WORKERS: usize = 16;
+const DEFAULT_QUEUE_SIZE: usize = 64;
+const DEFAULT_MAX_WEBSOCKETS: usize = 1024;
+const DEFAULT_SERVER_CORE: &str = "threads";
+const DEFAULT_STATIC_DIR: &str = "static";
+
+const HOME_TEMPLATE: &str = "templates/home.html";
+
+/// Heroku waits 30 seconds after SIGTERM before killing the process.
+const DEFAULT_SHUTDOWN_TIMEOUT: usize = 25;
+
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long a kept-alive connection may sit between requests.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client has to send the whole of a request once it starts.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Handlers = HashMap<&'static str, Arc<dyn websocket::Handler>>;
+
+/// Everything a connection needs to produce a response.
+struct App {
+    router: Router,
+    handlers: Handlers,
+    hub: Arc<Hub>,
+    /// The origins that may open WebSockets, or empty for any origin.
+    allowed_origins: Vec<String>,
+    max_body_size: usize,
+    max_websockets: usize,
+    open_websockets: AtomicUsize,
+    shutting_down: AtomicBool,
+}
+
+impl App {
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Whether to wait for another request on the connection after
+    /// answering this one.
+    fn keep_alive(&self, request: &request::Request) -> bool {
+        request.keep_alive() && !self.is_shutting_down()
+    }
+
+    /// Claims one of the `max_websockets` slots for a new WebSocket,
+    /// returning false if they are all in use or the server is
+    /// shutting down.
+    fn reserve_websocket(&self) -> bool {
+        if self.is_shutting_down() {
+            return false;
+        }
+
+        if self.open_websockets.fetch_add(1, Ordering::SeqCst) >= self.max_websockets {
+            self.release_websocket();
+            return false;
+        }
+
+        true
+    }
+
+    fn release_websocket(&self) {
+        self.open_websockets.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sends every message straight back to the client that sent it.
+struct Echo;
 
-fn write_response(request: request::Request, mut stream: TcpStream, routes: &HashMap<&str, String>) {
-    println!("REQUEST -- {} {} {}", request.method, request.target, request.http_version);
+impl websocket::Handler for Echo {
+    fn on_message(&self, socket: &websocket::Socket, message: websocket::Message) {
+        let _ = match message {
+            websocket::Message::Text(text) => socket.send_text(&text),
+            websocket::Message::Binary(data) => socket.send_binary(&data),
+            _ => Ok(()),
+        };
+    }
+}
+
+/// Relays every message to all of the connected clients.
+struct Broadcast {
+    hub: Arc<Hub>,
+}
+
+impl websocket::Handler for Broadcast {
+    fn on_message(&self, _socket: &websocket::Socket, message: websocket::Message) {
+        self.hub.broadcast(&message);
+    }
+}
+
+fn log_request(request: &request::Request) {
+    println!("REQUEST -- {} {} {}", request.method.as_str(), request.target, request.http_version);
     for (header, value) in &request.headers {
         println!("{}: \"{}\"", header, value);
     }
     println!("\nis_websocket: {}\n\n", request.is_websocket());
+}
+
+/// Works out the response to a plain HTTP request.
+fn respond(request: &mut request::Request, app: &App) -> Response {
+    match app.router.find(request.method, request.path()) {
+        Match::Found(handler, params) => {
+            request.params = params;
+            conditional::check(request, handler.handle(request))
+        },
+        Match::MethodNotAllowed(ref methods) if request.method == Method::Options => {
+            Response::new(Status::NoContent).with_header("Allow", &allow_header(methods))
+        },
+        Match::MethodNotAllowed(ref methods) => Response::new(Status::MethodNotAllowed)
+            .with_header("Allow", &allow_header(methods))
+            .with_body(Status::MethodNotAllowed.reason()),
+        Match::NotFound if request.method == Method::Options && request.target == "*" => {
+            Response::new(Status::NoContent).with_header("Allow", &allow_header(&Method::ALL))
+        },
+        Match::NotFound => Response::error(request::HTTPError::NotFound),
+    }
+}
+
+fn allow_header(methods: &[Method]) -> String {
+    let names: Vec<&str> = methods.iter().map(|method| method.as_str()).collect();
+    names.join(", ")
+}
+
+fn write_response<W: Write>(request: &mut request::Request, stream: &mut W, app: &App) -> io::Result<()> {
+    let response = respond(request, app);
+    send(response, request.method == Method::Head, app.keep_alive(request), stream)
+}
+
+/// Writes a response to the client. Everything the server sends before
+/// a WebSocket upgrade goes through here.
+fn send<W: Write>(response: Response, head_only: bool, keep_alive: bool, stream: &mut W) -> io::Result<()> {
+    with_connection_header(response, keep_alive).write_to(stream, head_only)
+}
 
-    if request.is_websocket() {
-        connect_websocket(request, stream);
+/// Tells the client when the connection will close after a response.
+fn with_connection_header(response: Response, keep_alive: bool) -> Response {
+    if keep_alive {
+        response
     } else {
-        match routes.get(request.target) {
-            Some(content) => {
-                let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/html;charset=utf-8\r\n\r\n";
-                let mut response = String::with_capacity(headers.len() + content.len());
-
-                response.push_str(headers);
-                response.push_str(&content);
-                stream.write(response.as_bytes()).expect("failed to write response");
-            },
-            None => write_error(request::HTTPError::NotFound, stream),
-        }
+        response.with_header("Connection", "close")
     }
 }
 
 const BONUS_STRING: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
-fn connect_websocket(request: request::Request, mut stream: TcpStream) {
-    if !request.headers.get("sec-websocket-version").map_or(false, |version| *version == "13") {
-        write_error(request::HTTPError::BadRequest, stream);
-        return;
+/// Answers the opening handshake, returning the connection if the
+/// upgrade went ahead.
+fn connect_websocket(request: request::Request, buffered: Vec<u8>, mut stream: TcpStream, handler: &dyn websocket::Handler, app: &App) -> Option<websocket::Connection> {
+    match websocket_handshake(&request, handler, app) {
+        Ok((response, mut handshake)) => {
+            if send(response, false, true, &mut stream).is_err() {
+                return None;
+            }
+
+            handshake.buffered = buffered;
+            websocket::Connection::new(stream, handshake).ok()
+        },
+        Err(error) => {
+            write_error(error, &mut stream);
+            None
+        },
+    }
+}
+
+/// Checks an upgrade request against the endpoint, returning the 101
+/// response to send along with what was agreed on, or the status to
+/// refuse it with. Every endpoint is held to `ALLOWED_ORIGINS`, as
+/// well as its own `allows_origin`.
+fn websocket_handshake(request: &request::Request, handler: &dyn websocket::Handler, app: &App) -> Result<(Response, websocket::Handshake), request::HTTPError> {
+    if request.headers.get("sec-websocket-version") != Some(&"13") {
+        return Err(request::HTTPError::BadRequest);
     }
 
     let websocket_key = match request.headers.get("sec-websocket-key") {
         Some(key) => key,
         None => {
-            write_error(request::HTTPError::BadRequest, stream);
-            return;
+            return Err(request::HTTPError::BadRequest);
         },
     };
 
+    let origin = request.headers.get("origin").cloned();
+    let allowed = app.allowed_origins.is_empty() || websocket::origin_allowed(origin, &app.allowed_origins);
+
+    if !allowed || !handler.allows_origin(origin) {
+        return Err(request::HTTPError::Forbidden);
+    }
+
+    let identity = handler.authorize(request)?;
+
+    let protocol = request.headers.get("sec-websocket-protocol")
+        .and_then(|offered| websocket::select_protocol(offered, handler.protocols()));
+
+    if protocol.is_none() && handler.requires_protocol() {
+        return Err(request::HTTPError::BadRequest);
+    }
+
+    let deflate = request.headers.get("sec-websocket-extensions")
+        .and_then(|extensions| DeflateParams::negotiate(extensions));
+
     let websocket_key = websocket_key.to_string() + BONUS_STRING;
 
     println!("websocket key: {}", websocket_key);
 
     let mut digester = sha1::SHA1Context::new();
-    digester.add(&websocket_key.as_bytes());
+    digester.add(websocket_key.as_bytes());
     let websocket_key_bytes = digester.digest();
 
     let encoded_websocket_key = base64::encode(&websocket_key_bytes);
 
-    let mut output_buffer: Vec<u8> = Vec::new();
+    let mut response = Response::new(Status::SwitchingProtocols)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", str::from_utf8(&encoded_websocket_key).unwrap());
+
+    if let Some(protocol) = protocol {
+        response.set_header("Sec-WebSocket-Protocol", protocol);
+    }
+
+    if let Some(ref deflate) = deflate {
+        response.set_header("Sec-WebSocket-Extensions", &deflate.response());
+    }
 
-    output_buffer.extend_from_slice(b"HTTP/1.1 101 Switching Protocols\r\n");
-    output_buffer.extend_from_slice(b"Upgrade: websocket\r\n");
-    output_buffer.extend_from_slice(b"Connection: Upgrade\r\n");
-    output_buffer.extend_from_slice(b"Sec-WebSocket-Accept: ");
-    output_buffer.extend_from_slice(&encoded_websocket_key);
-    output_buffer.extend_from_slice(b"\r\n\r\n");
+    Ok((response, websocket::Handshake { protocol, deflate, identity, buffered: Vec::new() }))
+}
 
-    stream.write(&output_buffer).expect("oh no");
+/// Errors always close the connection afterwards, since the rest of
+/// what the client sent can't be trusted to start a new request.
+fn write_error<W: Write>(error: request::HTTPError, stream: &mut W) {
+    let _ = send(Response::error(error), false, false, stream);
 }
 
-fn write_error(error: request::HTTPError, mut stream: TcpStream) {
-    let contents: &[u8] = match error {
-        request::HTTPError::BadRequest => b"HTTP/1.1 400 Bad Request\r\n\r\nBad Request",
-        request::HTTPError::NotImplemented => b"HTTP/1.1 501 Not Implemented\r\n\r\nNot Implemented",
-        request::HTTPError::VersionNotSupported => b"HTTP/1.1 505 HTTP Version Not Supported\r\n\r\nHTTP Version Not Supported",
-        request::HTTPError::NotFound => b"HTTP/1.1 404 Not Found\r\n\r\nNot Found",
-    };
+/// Reads more of a request into the buffer, giving up with 408 once
+/// the deadline for the whole request has passed.
+fn read_more(stream: &mut TcpStream, buffer: &mut Vec<u8>, deadline: Instant) -> Result<(), request::HTTPError> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(request::HTTPError::RequestTimeout);
+    }
+
+    let mut chunk = [0; 4096];
+    let _ = stream.set_read_timeout(Some(deadline - now));
 
-    stream.write(contents).expect("failed to write response");
+    match stream.read(&mut chunk) {
+        Ok(0) => Err(request::HTTPError::BadRequest),
+        Ok(count) => {
+            buffer.extend_from_slice(&chunk[..count]);
+            Ok(())
+        },
+        Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut => {
+            Err(request::HTTPError::RequestTimeout)
+        },
+        Err(_) => Err(request::HTTPError::BadRequest),
+    }
+}
+
+/// Waits up to `IDLE_TIMEOUT` for the next request to start. Returns
+/// false if the client closed the connection or never sent anything.
+fn wait_for_request(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> bool {
+    if !buffer.is_empty() {
+        return true;
+    }
+
+    let mut chunk = [0; 4096];
+    let _ = stream.set_read_timeout(Some(IDLE_TIMEOUT));
+
+    match stream.read(&mut chunk) {
+        Ok(0) | Err(_) => false,
+        Ok(count) => {
+            buffer.extend_from_slice(&chunk[..count]);
+            true
+        },
+    }
 }
 
-fn handle_client(mut stream: TcpStream, routes: &HashMap<&str, String>) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).expect("read failed");
+/// Reads from the stream until the buffer holds the whole request line
+/// and headers, returning their length.
+fn read_head(stream: &mut TcpStream, buffer: &mut Vec<u8>, deadline: Instant) -> Result<usize, request::HTTPError> {
+    loop {
+        if let Some(length) = request::head_length(buffer)? {
+            return Ok(length);
+        }
 
-    match request::parse_request(&buffer) {
-        Ok(request) => write_response(request, stream, routes),
-        Err(error) => write_error(error, stream),
+        read_more(stream, buffer, deadline)?;
+    }
+}
+
+/// Reads the request body framed as the headers describe. `buffered`
+/// holds whatever arrived along with the headers. Returns the body and
+/// any bytes read past its end.
+fn read_body(request: &request::Request, mut buffered: Vec<u8>, stream: &mut TcpStream, max_body_size: usize, deadline: Instant) -> Result<(Vec<u8>, Vec<u8>), request::HTTPError> {
+    match request.body_length()? {
+        request::BodyLength::Empty => Ok((Vec::new(), buffered)),
+        request::BodyLength::Fixed(length) => {
+            if length > max_body_size {
+                return Err(request::HTTPError::PayloadTooLarge);
+            }
+
+            while buffered.len() < length {
+                read_more(stream, &mut buffered, deadline)?;
+            }
+
+            let rest = buffered.split_off(length);
+            Ok((buffered, rest))
+        },
+        request::BodyLength::Chunked => {
+            let mut decoder = request::ChunkedDecoder::new(max_body_size);
+
+            loop {
+                if let Some((body, length)) = decoder.decode(&buffered)? {
+                    return Ok((body, buffered.split_off(length)));
+                }
+
+                read_more(stream, &mut buffered, deadline)?;
+            }
+        },
+    }
+}
+
+/// Serves requests from the connection one after another until the
+/// client asks to close it, goes quiet or upgrades to a WebSocket.
+/// Pipelined requests are answered in the order they arrived.
+///
+/// A connection accepted before shutdown began still has its request
+/// answered, with `Connection: close`. One that has already been
+/// answered only gets the requests it sent before then.
+fn handle_client(mut stream: TcpStream, app: &Arc<App>) {
+    let mut buffer = Vec::new();
+    let mut answered = false;
+
+    loop {
+        if answered && buffer.is_empty() && app.is_shutting_down() {
+            return;
+        }
+
+        if !wait_for_request(&mut stream, &mut buffer) {
+            return;
+        }
+
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+
+        let header_length = match read_head(&mut stream, &mut buffer, deadline) {
+            Ok(length) => length,
+            Err(error) => return write_error(error, &mut stream),
+        };
+
+        let mut request = match request::parse_request(&buffer[..header_length]) {
+            Ok(request) => request,
+            Err(error) => return write_error(error, &mut stream),
+        };
+
+        let buffered = buffer[header_length..].to_vec();
+
+        let buffered = match read_body(&request, buffered, &mut stream, app.max_body_size, deadline) {
+            Ok((body, buffered)) => {
+                request.body = body;
+                buffered
+            },
+            Err(error) => return write_error(error, &mut stream),
+        };
+
+        log_request(&request);
+
+        if request.is_websocket() {
+            return upgrade(request, buffered, stream, app);
+        }
+
+        if write_response(&mut request, &mut stream, app).is_err() || !app.keep_alive(&request) {
+            return;
+        }
+
+        answered = true;
+        buffer = buffered;
+    }
+}
+
+/// Hands an upgraded connection over to its own thread. WebSockets
+/// stay open for as long as the client likes, so serving them from
+/// the worker pool would soon leave no workers for plain requests.
+fn upgrade(request: request::Request, buffered: Vec<u8>, mut stream: TcpStream, app: &Arc<App>) {
+    let handler = match app.handlers.get(request.path()) {
+        Some(handler) => handler.clone(),
+        None => return write_error(request::HTTPError::NotFound, &mut stream),
     };
+
+    if !app.reserve_websocket() {
+        return write_error(request::HTTPError::ServiceUnavailable, &mut stream);
+    }
+
+    // The connection may sit idle for as long as it likes from here on.
+    let _ = stream.set_read_timeout(None);
+
+    match connect_websocket(request, buffered, stream, handler.as_ref(), app) {
+        Some(connection) => {
+            let app = app.clone();
+
+            thread::spawn(move || {
+                websocket::serve(connection, handler.as_ref(), &app.hub);
+                app.release_websocket();
+            });
+        },
+        None => app.release_websocket(),
+    }
+}
+
+/// Reads a positive number from the environment, falling back to the
+/// default if the variable isn't set.
+fn env_usize(name: &str, default: usize) -> usize {
+    match env::var(name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(value) if value > 0 => value,
+            _ => panic!("${} is not a positive integer", name),
+        },
+        Err(_) => default,
+    }
 }
 
 fn read_file(path: &str) -> String {
-    let mut file = File::open(path).expect(&format!("Could not open file at {}", path));
+    let mut file = File::open(path).unwrap_or_else(|_| panic!("Could not open file at {}", path));
     let mut contents = String::new();
-    file.read_to_string(&mut contents).expect(&format!("Failed to read {}", path));
+    file.read_to_string(&mut contents).unwrap_or_else(|_| panic!("Failed to read {}", path));
     contents
 }
 
@@ -107,19 +459,245 @@ fn main() {
         Err(_) => DEFAULT_PORT
     };
 
-    let mut routes: HashMap<&str, String> = HashMap::new();
-    routes.insert("/", read_file("templates/home.html"));
+    let max_body_size = env_usize("MAX_BODY_SIZE", DEFAULT_MAX_BODY_SIZE);
+    let max_websockets = env_usize("MAX_WEBSOCKETS", DEFAULT_MAX_WEBSOCKETS);
+
+    // WORKERS threads serve plain HTTP requests, with up to QUEUE_SIZE
+    // more connections waiting for one to be free. Any beyond that are
+    // turned away with a 503.
+    let workers = env_usize("WORKERS", DEFAULT_WORKERS);
+    let queue_size = env_usize("QUEUE_SIZE", DEFAULT_QUEUE_SIZE);
+
+    // The template is read once, so its tag and modification time
+    // can be worked out up front too.
+    let home = read_file(HOME_TEMPLATE);
+    let home_etag = conditional::etag(home.as_bytes());
+    let home_modified = fs::metadata(HOME_TEMPLATE).and_then(|metadata| metadata.modified())
+        .map(format_http_date)
+        .unwrap_or_else(|_| format_http_date(SystemTime::now()));
+
+    let mut router = Router::new();
+    router.get("/", move |_: &request::Request| {
+        Response::new(Status::Ok)
+            .with_header("Content-Type", "text/html;charset=utf-8")
+            .with_header("ETag", &home_etag)
+            .with_header("Last-Modified", &home_modified)
+            .with_body(home.as_str())
+    }).unwrap_or_else(|error| panic!("{}", error));
+
+    // Files under STATIC_DIR are served from /static/, if it exists.
+    let static_dir = env::var("STATIC_DIR").unwrap_or_else(|_| DEFAULT_STATIC_DIR.to_string());
+    match StaticFiles::new(&static_dir) {
+        Ok(files) => router.get("/static/*path", files).unwrap_or_else(|error| panic!("{}", error)),
+        Err(error) => println!("not serving static files from {}: {}", static_dir, error),
+    }
+
+    // A comma-separated list of origins, such as
+    // "https://example.com,http://localhost:4485", that may open
+    // sockets on any endpoint. If unset, any origin may connect.
+    let allowed_origins: Vec<String> = match env::var("ALLOWED_ORIGINS") {
+        Ok(origins) => origins.split(',')
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let hub = Arc::new(Hub::new());
+
+    let mut handlers: Handlers = HashMap::new();
+    handlers.insert("/socket", Arc::new(Broadcast { hub: hub.clone() }));
+    handlers.insert("/echo", Arc::new(Echo));
+
+    let app = Arc::new(App {
+        router,
+        handlers,
+        hub,
+        allowed_origins,
+        max_body_size,
+        max_websockets,
+        open_websockets: AtomicUsize::new(0),
+        shutting_down: AtomicBool::new(false),
+    });
 
     let listener = TcpListener::bind(("0.0.0.0", port)).unwrap();
 
+    let shutdown_timeout = Duration::from_secs(env_usize("SHUTDOWN_TIMEOUT", DEFAULT_SHUTDOWN_TIMEOUT) as u64);
+
+    #[cfg(unix)]
+    watch_for_termination(app.clone(), port, shutdown_timeout);
+
+    // "threads" serves connections from the worker pool. "epoll"
+    // serves them all from one thread, which copes better with many
+    // idle WebSockets.
+    let core = env::var("SERVER_CORE").unwrap_or_else(|_| DEFAULT_SERVER_CORE.to_string());
+
+    match core.as_str() {
+        "threads" => serve_with_threads(listener, &app, workers, queue_size),
+        #[cfg(target_os = "linux")]
+        "epoll" => event_loop::run(listener, app.clone()).expect("event loop failed"),
+        _ => panic!("$SERVER_CORE must be threads{}", if cfg!(target_os = "linux") { " or epoll" } else { "" }),
+    }
+
+    while app.open_websockets.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    println!("shutdown complete");
+}
+
+/// Accepts connections until the server starts shutting down, then
+/// waits for the requests already accepted to be answered.
+fn serve_with_threads(listener: TcpListener, app: &Arc<App>, workers: usize, queue_size: usize) {
+    let pool = {
+        let app = app.clone();
+        WorkerPool::new(workers, queue_size, move |stream| handle_client(stream, &app))
+    };
+
     for stream in listener.incoming() {
+        if app.is_shutting_down() {
+            break;
+        }
+
         match stream {
             Ok(stream) => {
-                handle_client(stream, &routes);
+                if let Err(mut stream) = pool.submit(stream) {
+                    write_error(request::HTTPError::ServiceUnavailable, &mut stream);
+                }
             }
-            Err(_) => {
-                panic!("error accepting connection");
+            Err(error) => {
+                // Running out of file descriptors is usually temporary,
+                // so back off rather than spinning or giving up.
+                println!("error accepting connection: {}", error);
+                thread::sleep(ACCEPT_BACKOFF);
             }
         }
     }
+
+    drop(listener);
+    drop(pool);
+}
+
+/// Starts a thread that begins a graceful shutdown on SIGTERM or
+/// SIGINT: no more connections are accepted, requests in progress are
+/// answered and WebSockets are closed with 1001 (Going Away). If that
+/// takes longer than `timeout`, the process exits anyway.
+#[cfg(unix)]
+fn watch_for_termination(app: Arc<App>, port: u16, timeout: Duration) {
+    signal::catch_termination().expect("failed to catch SIGTERM and SIGINT");
+
+    thread::spawn(move || {
+        if signal::wait().is_err() {
+            return;
+        }
+
+        println!("shutting down");
+        app.shutting_down.store(true, Ordering::SeqCst);
+
+        // Wake the accept loop so it notices.
+        let _ = TcpStream::connect(("127.0.0.1", port));
+
+        app.hub.close_all(websocket::CLOSE_GOING_AWAY, "Server shutting down");
+
+        thread::sleep(timeout);
+        println!("shutdown took longer than {} seconds, exiting", timeout.as_secs());
+        process::exit(1);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> Arc<App> {
+        let mut router = Router::new();
+        router.get("/", |_: &request::Request| Response::new(Status::Ok).with_body("home")).unwrap();
+
+        let mut handlers: Handlers = HashMap::new();
+        handlers.insert("/echo", Arc::new(Echo));
+
+        Arc::new(App {
+            router,
+            handlers,
+            hub: Arc::new(Hub::new()),
+            allowed_origins: vec!["https://example.com".to_string()],
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_websockets: DEFAULT_MAX_WEBSOCKETS,
+            open_websockets: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+        })
+    }
+
+    #[test]
+    fn handle_client_answers_queued_connection_during_shutdown() {
+        let app = app();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let pool = {
+            let app = app.clone();
+            WorkerPool::new(1, 2, move |stream| handle_client(stream, &app))
+        };
+
+        // The only worker waits on a connection that sends nothing, so
+        // the next one is left in the queue.
+        let idle = TcpStream::connect(address).unwrap();
+        pool.submit(listener.accept().unwrap().0).unwrap();
+
+        let mut client = TcpStream::connect(address).unwrap();
+        pool.submit(listener.accept().unwrap().0).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        app.shutting_down.store(true, Ordering::SeqCst);
+        drop(idle);
+
+        let mut response = String::new();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.read_to_string(&mut response).unwrap();
+        drop(pool);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Connection: close\r\n"), "{}", response);
+        assert!(response.ends_with("home"), "{}", response);
+    }
+
+    /// Sends the request on a new connection served by `handle_client`
+    /// and returns everything written back.
+    fn exchange(app: &Arc<App>, input: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        client.write_all(input.as_bytes()).unwrap();
+        handle_client(server, app);
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        output
+    }
+
+    fn upgrade_request(path: &str, origin: &str) -> String {
+        format!("GET {} HTTP/1.1\r\nHost: localhost\r\nOrigin: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n", path, origin)
+    }
+
+    #[test]
+    fn echo_refuses_origins_that_arent_allowed() {
+        let app = app();
+        let output = exchange(&app, &upgrade_request("/echo", "https://evil.example"));
+
+        assert!(output.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", output);
+        assert_eq!(app.open_websockets.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn websocket_handshake_accepts_allowed_origins() {
+        let app = app();
+        let input = upgrade_request("/echo", "https://example.com");
+        let request = request::parse_request(input.as_bytes()).unwrap();
+        let (response, _) = websocket_handshake(&request, &Echo, &app).unwrap();
+
+        assert_eq!(response.status, Status::SwitchingProtocols);
+        assert_eq!(response.header("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+    }
 }
@@ -0,0 +1,259 @@
+use deflate::{Deflater, InflateError, Inflater};
+use websocket::FrameError;
+
+/// Every compressed message ends with these bytes, which are left off
+/// on the wire and added back before decompressing. See Section 7.2.1
+/// of RFC 7692.
+const TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+const DEFAULT_WINDOW_BITS: u8 = 15;
+
+/// The permessage-deflate parameters agreed on with a client.
+#[derive(PartialEq, Debug, Clone)]
+pub struct DeflateParams {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+    pub server_max_window_bits: Option<u8>,
+}
+
+impl DeflateParams {
+    /// Picks the first permessage-deflate offer in a
+    /// Sec-WebSocket-Extensions header that we can accept. Offers with
+    /// unknown, repeated or malformed parameters are declined as
+    /// required by Section 7.1 of RFC 7692.
+    pub fn negotiate(header: &str) -> Option<DeflateParams> {
+        header.split(',').filter_map(parse_offer).next()
+    }
+
+    /// The value of the Sec-WebSocket-Extensions header that accepts
+    /// this offer.
+    pub fn response(&self) -> String {
+        let mut response = "permessage-deflate".to_string();
+
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+
+        if self.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+
+        if let Some(bits) = self.server_max_window_bits {
+            response.push_str(&format!("; server_max_window_bits={}", bits));
+        }
+
+        response
+    }
+}
+
+fn parse_offer(offer: &str) -> Option<DeflateParams> {
+    let mut tokens = offer.split(';').map(|token| token.trim());
+
+    if tokens.next() != Some("permessage-deflate") {
+        return None;
+    }
+
+    let mut params = DeflateParams {
+        server_no_context_takeover: false,
+        client_no_context_takeover: false,
+        server_max_window_bits: None,
+    };
+    let mut client_max_window_bits = false;
+
+    for token in tokens {
+        let mut parts = token.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().map(|value| value.trim().trim_matches('"'));
+
+        match (name, value) {
+            ("server_no_context_takeover", None) if !params.server_no_context_takeover => {
+                params.server_no_context_takeover = true;
+            },
+            ("client_no_context_takeover", None) if !params.client_no_context_takeover => {
+                params.client_no_context_takeover = true;
+            },
+            ("server_max_window_bits", Some(value)) if params.server_max_window_bits.is_none() => {
+                params.server_max_window_bits = Some(parse_window_bits(value)?);
+            },
+            // The client's window only limits what it sends us, and
+            // the inflater always keeps a full 32K window, so there is
+            // nothing to answer with beyond checking the value.
+            ("client_max_window_bits", value) if !client_max_window_bits => {
+                if let Some(value) = value {
+                    parse_window_bits(value)?;
+                }
+                client_max_window_bits = true;
+            },
+            _ => return None,
+        }
+    }
+
+    Some(params)
+}
+
+fn parse_window_bits(value: &str) -> Option<u8> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    match value.parse() {
+        Ok(bits) if (8..=15).contains(&bits) => Some(bits),
+        _ => None,
+    }
+}
+
+/// Compresses the payloads of outgoing messages.
+pub struct Compressor {
+    deflater: Deflater,
+    no_context_takeover: bool,
+}
+
+impl Compressor {
+    pub fn new(params: &DeflateParams) -> Compressor {
+        let window_bits = params.server_max_window_bits.unwrap_or(DEFAULT_WINDOW_BITS);
+
+        Compressor {
+            deflater: Deflater::new(window_bits),
+            no_context_takeover: params.server_no_context_takeover,
+        }
+    }
+
+    pub fn compress(&mut self, payload: &[u8]) -> Vec<u8> {
+        if self.no_context_takeover {
+            self.deflater.reset();
+        }
+
+        let mut output = self.deflater.deflate(payload);
+        output.truncate(output.len() - TAIL.len());
+        output
+    }
+}
+
+/// Decompresses the payloads of incoming messages.
+pub struct Decompressor {
+    inflater: Inflater,
+    no_context_takeover: bool,
+}
+
+impl Decompressor {
+    pub fn new(params: &DeflateParams) -> Decompressor {
+        Decompressor { inflater: Inflater::new(), no_context_takeover: params.client_no_context_takeover }
+    }
+
+    pub fn decompress(&mut self, payload: &[u8], max_length: usize) -> Result<Vec<u8>, FrameError> {
+        if self.no_context_takeover {
+            self.inflater.reset();
+        }
+
+        let mut input = Vec::with_capacity(payload.len() + TAIL.len());
+        input.extend_from_slice(payload);
+        input.extend_from_slice(&TAIL);
+
+        match self.inflater.inflate(&input, max_length) {
+            Ok(output) => Ok(output),
+            Err(InflateError::TooLarge) => Err(FrameError::MessageTooBig),
+            Err(InflateError::InvalidData) => Err(FrameError::InvalidPayload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> DeflateParams {
+        DeflateParams {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            server_max_window_bits: None,
+        }
+    }
+
+    #[test]
+    fn negotiate_accepts_offer_without_parameters() {
+        let params = DeflateParams::negotiate("permessage-deflate").unwrap();
+
+        assert_eq!(params, self::params());
+        assert_eq!(params.response(), "permessage-deflate");
+    }
+
+    #[test]
+    fn negotiate_accepts_offer_with_every_parameter() {
+        let header = "permessage-deflate; server_no_context_takeover; client_no_context_takeover; server_max_window_bits=10; client_max_window_bits";
+        let params = DeflateParams::negotiate(header).unwrap();
+
+        assert!(params.server_no_context_takeover);
+        assert!(params.client_no_context_takeover);
+        assert_eq!(params.server_max_window_bits, Some(10));
+        assert_eq!(params.response(), "permessage-deflate; server_no_context_takeover; client_no_context_takeover; server_max_window_bits=10");
+    }
+
+    #[test]
+    fn negotiate_accepts_quoted_values() {
+        let params = DeflateParams::negotiate("permessage-deflate; server_max_window_bits=\"12\"").unwrap();
+        assert_eq!(params.server_max_window_bits, Some(12));
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_later_offers() {
+        let header = "x-webkit-deflate-frame, permessage-deflate; server_max_window_bits=16, permessage-deflate; client_max_window_bits";
+        let params = DeflateParams::negotiate(header).unwrap();
+
+        assert_eq!(params, self::params());
+    }
+
+    #[test]
+    fn negotiate_declines_invalid_offers() {
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; foo"), None);
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; server_max_window_bits"), None);
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; server_max_window_bits=7"), None);
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; server_max_window_bits=+9"), None);
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; client_max_window_bits=16"), None);
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; server_no_context_takeover=1"), None);
+        assert_eq!(DeflateParams::negotiate("permessage-deflate; client_no_context_takeover; client_no_context_takeover"), None);
+        assert_eq!(DeflateParams::negotiate("x-webkit-deflate-frame"), None);
+    }
+
+    #[test]
+    fn compressor_leaves_off_trailing_bytes() {
+        let mut compressor = Compressor::new(&params());
+        assert_eq!(compressor.compress(b"Hello"), vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+    }
+
+    #[test]
+    fn compressor_and_decompressor_keep_context_between_messages() {
+        let mut compressor = Compressor::new(&params());
+        let mut decompressor = Decompressor::new(&params());
+
+        let first = compressor.compress(b"Hello");
+        let second = compressor.compress(b"Hello");
+
+        assert!(second.len() < first.len());
+        assert_eq!(decompressor.decompress(&first, 1024).unwrap(), b"Hello");
+        assert_eq!(decompressor.decompress(&second, 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn compressor_without_context_takeover_compresses_each_message_alone() {
+        let mut params = params();
+        params.server_no_context_takeover = true;
+        let mut compressor = Compressor::new(&params);
+
+        assert_eq!(compressor.compress(b"Hello"), compressor.compress(b"Hello"));
+    }
+
+    #[test]
+    fn decompressor_reports_bad_data_as_invalid_payload() {
+        let mut decompressor = Decompressor::new(&params());
+        assert_eq!(decompressor.decompress(&[0xFF, 0xFF], 1024), Err(FrameError::InvalidPayload));
+    }
+
+    #[test]
+    fn decompressor_reports_oversized_output_as_too_big() {
+        let mut compressor = Compressor::new(&params());
+        let mut decompressor = Decompressor::new(&params());
+        let compressed = compressor.compress(&[0; 4096]);
+
+        assert_eq!(decompressor.decompress(&compressed, 1024), Err(FrameError::MessageTooBig));
+    }
+}
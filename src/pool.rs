@@ -0,0 +1,142 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A fixed number of threads working through a bounded queue of items,
+/// such as accepted connections. Once every thread is busy and the
+/// queue is full, new items are handed straight back so the caller can
+/// turn them away instead of leaving them to wait.
+pub struct WorkerPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Starts `size` threads that each call `work` on the items they
+    /// take from the queue. Up to `queue_size` items may wait for a
+    /// free thread.
+    pub fn new<F>(size: usize, queue_size: usize, work: F) -> WorkerPool<T>
+        where F: Fn(T) + Send + Sync + 'static
+    {
+        assert!(size > 0, "a worker pool needs at least one thread");
+
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let work = Arc::new(work);
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let work = work.clone();
+                thread::spawn(move || run_worker(&receiver, &*work))
+            })
+            .collect();
+
+        WorkerPool { sender: Some(sender), workers }
+    }
+
+    /// Queues the item for the next free thread, or returns it if the
+    /// queue is already full.
+    pub fn submit(&self, item: T) -> Result<(), T> {
+        let sender = self.sender.as_ref().expect("worker pool is shutting down");
+
+        match sender.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) | Err(TrySendError::Disconnected(item)) => Err(item),
+        }
+    }
+}
+
+/// Dropping the pool lets the threads finish what is already queued
+/// and then waits for them to exit.
+impl<T: Send + 'static> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run_worker<T, F: Fn(T)>(receiver: &Mutex<Receiver<T>>, work: &F) {
+    loop {
+        // The lock is released as soon as an item arrives, so the other
+        // threads can take the next one while this one works.
+        let item = receiver.lock().unwrap().recv();
+
+        match item {
+            // A panic while handling one item shouldn't cost the pool
+            // a thread.
+            Ok(item) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| work(item)));
+            },
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn pool_works_through_every_item() {
+        let (results, received) = channel();
+        let results = Mutex::new(results);
+        let pool = WorkerPool::new(4, 16, move |item: usize| {
+            results.lock().unwrap().send(item * 2).unwrap();
+        });
+
+        for item in 0..16 {
+            pool.submit(item).unwrap();
+        }
+        drop(pool);
+
+        let mut doubled: Vec<usize> = received.iter().collect();
+        doubled.sort();
+        assert_eq!(doubled, (0..16).map(|item| item * 2).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn submit_returns_item_when_pool_is_saturated() {
+        let (release, blocked) = channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let (started, running) = channel();
+        let started = Mutex::new(started);
+
+        let pool = WorkerPool::new(1, 1, move |_: usize| {
+            started.lock().unwrap().send(()).unwrap();
+            blocked.lock().unwrap().recv().unwrap();
+        });
+
+        pool.submit(1).unwrap();
+        running.recv_timeout(Duration::from_secs(5)).unwrap();
+        pool.submit(2).unwrap();
+
+        assert_eq!(pool.submit(3), Err(3));
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn pool_keeps_working_after_a_panic() {
+        let (results, received) = channel();
+        let results = Mutex::new(results);
+        let pool = WorkerPool::new(1, 4, move |item: usize| {
+            if item == 0 {
+                panic!("failed to handle item");
+            }
+            results.lock().unwrap().send(item).unwrap();
+        });
+
+        pool.submit(0).unwrap();
+        pool.submit(1).unwrap();
+
+        assert_eq!(received.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+}
@@ -0,0 +1,467 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::time::SystemTime;
+use date::format_http_date;
+use request::HTTPError;
+
+const SERVER: &str = "strudel";
+
+const CHUNK_SIZE: usize = 8192;
+
+/// The status codes the server sends, from Section 6 of RFC 7231 and
+/// the RFCs that added to it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Status {
+    SwitchingProtocols,
+    Ok,
+    NoContent,
+    NotModified,
+    BadRequest,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    PayloadTooLarge,
+    HeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    VersionNotSupported,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::SwitchingProtocols => 101,
+            Status::Ok => 200,
+            Status::NoContent => 204,
+            Status::NotModified => 304,
+            Status::BadRequest => 400,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::RequestTimeout => 408,
+            Status::PayloadTooLarge => 413,
+            Status::HeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
+            Status::VersionNotSupported => 505,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::SwitchingProtocols => "Switching Protocols",
+            Status::Ok => "OK",
+            Status::NoContent => "No Content",
+            Status::NotModified => "Not Modified",
+            Status::BadRequest => "Bad Request",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::RequestTimeout => "Request Timeout",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::HeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::VersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    /// 1xx, 204 and 304 responses never have a body, nor a
+    /// Content-Length describing one. See Section 3.3 of RFC 7230.
+    fn allows_body(self) -> bool {
+        let code = self.code();
+        code >= 200 && code != 204 && code != 304
+    }
+}
+
+impl From<HTTPError> for Status {
+    fn from(error: HTTPError) -> Status {
+        match error {
+            HTTPError::BadRequest => Status::BadRequest,
+            HTTPError::NotImplemented => Status::NotImplemented,
+            HTTPError::VersionNotSupported => Status::VersionNotSupported,
+            HTTPError::NotFound => Status::NotFound,
+            HTTPError::Forbidden => Status::Forbidden,
+            HTTPError::RequestTimeout => Status::RequestTimeout,
+            HTTPError::PayloadTooLarge => Status::PayloadTooLarge,
+            HTTPError::HeaderFieldsTooLarge => Status::HeaderFieldsTooLarge,
+            HTTPError::ServiceUnavailable => Status::ServiceUnavailable,
+            HTTPError::InternalServerError => Status::InternalServerError,
+        }
+    }
+}
+
+/// A body of unknown length, read as it is sent.
+pub type BodyReader = Box<dyn Read + Send>;
+
+/// What follows the headers. Files are sent with a Content-Length taken
+/// from their size, while readers of unknown length are streamed with
+/// the chunked transfer coding.
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    File(File),
+    Reader(BodyReader),
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for Body {
+    fn from(text: &'a str) -> Body {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+impl From<File> for Body {
+    fn from(file: File) -> Body {
+        Body::File(file)
+    }
+}
+
+/// A response waiting to be sent. The server fills in Date, Server and
+/// the framing headers when it is written out.
+pub struct Response {
+    pub status: Status,
+    headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn new(status: Status) -> Response {
+        Response { status, headers: Vec::new(), body: Body::Empty }
+    }
+
+    /// An error response whose body is the reason phrase.
+    pub fn error(error: HTTPError) -> Response {
+        let status = Status::from(error);
+        Response::new(status).with_body(status.reason())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.set_header(name, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Body>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets a header, replacing any value it already had. Names are
+    /// compared without regard to case.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|header| header.0.eq_ignore_ascii_case(name)) {
+            Some(header) => header.1 = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| header.1.as_str())
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.retain(|header| !header.0.eq_ignore_ascii_case(name));
+    }
+
+    /// Serializes the response. For a HEAD request `head_only` leaves
+    /// off the body but keeps the headers that describe it.
+    pub fn write_to<W: Write>(self, stream: &mut W, head_only: bool) -> io::Result<()> {
+        let (head, body) = self.into_parts(head_only)?;
+        stream.write_all(&head)?;
+
+        match body {
+            Some(mut body) => io::copy(&mut body, stream).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Serializes the status line and headers, returning them along
+    /// with whatever of the body has to be read from a file or reader.
+    /// Bodies already in memory are sent with the head. Lets a caller
+    /// that can't block write the rest as the client makes room.
+    pub fn into_parts(mut self, head_only: bool) -> io::Result<(Vec<u8>, Option<BodyReader>)> {
+        if self.header("date").is_none() {
+            self.set_header("Date", &format_http_date(SystemTime::now()));
+        }
+
+        if self.header("server").is_none() {
+            self.set_header("Server", SERVER);
+        }
+
+        self.remove_header("content-length");
+        self.remove_header("transfer-encoding");
+
+        // A file that changes size while it's being sent is still held
+        // to the length given here. See `FileReader`.
+        let mut file_length = 0;
+
+        if self.status.allows_body() {
+            match self.body {
+                Body::Empty => self.set_header("Content-Length", "0"),
+                Body::Bytes(ref bytes) => self.set_header("Content-Length", &bytes.len().to_string()),
+                Body::File(ref file) => {
+                    file_length = file.metadata()?.len();
+                    self.set_header("Content-Length", &file_length.to_string());
+                },
+                Body::Reader(_) => self.set_header("Transfer-Encoding", "chunked"),
+            }
+        }
+
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status.code(), self.status.reason());
+        for (name, value) in &self.headers {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+
+        let mut head = head.into_bytes();
+
+        if head_only || !self.status.allows_body() {
+            return Ok((head, None));
+        }
+
+        match self.body {
+            Body::Empty => Ok((head, None)),
+            Body::Bytes(bytes) => {
+                head.extend_from_slice(&bytes);
+                Ok((head, None))
+            },
+            Body::File(file) => Ok((head, Some(Box::new(FileReader { file, remaining: file_length })))),
+            Body::Reader(reader) => Ok((head, Some(Box::new(ChunkedReader::new(reader))))),
+        }
+    }
+}
+
+/// Reads a file body up to the Content-Length sent for it. A file that
+/// has grown since stops there, since the client would read the excess
+/// as the start of the next response. One that has shrunk fails with
+/// `UnexpectedEof` rather than ending early, so the connection is
+/// closed instead of being left short of what was promised.
+struct FileReader {
+    file: File,
+    remaining: u64,
+}
+
+impl Read for FileReader {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+
+        let limit = if self.remaining < output.len() as u64 { self.remaining as usize } else { output.len() };
+        let count = self.file.read(&mut output[..limit])?;
+
+        if count == 0 && limit > 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended before its Content-Length"));
+        }
+
+        self.remaining -= count as u64;
+        Ok(count)
+    }
+}
+
+/// Encodes what it reads with the chunked transfer coding from Section
+/// 4.1 of RFC 7230, ending with the last chunk once the reader runs
+/// out.
+struct ChunkedReader<R> {
+    reader: R,
+    chunk: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> ChunkedReader<R> {
+    fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader { reader, chunk: Vec::new(), position: 0, finished: false }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut buffer = [0; CHUNK_SIZE];
+
+        let count = loop {
+            match self.reader.read(&mut buffer) {
+                Ok(count) => break count,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => return Err(error),
+            }
+        };
+
+        self.position = 0;
+
+        if count == 0 {
+            self.chunk = b"0\r\n\r\n".to_vec();
+            self.finished = true;
+        } else {
+            self.chunk = format!("{:X}\r\n", count).into_bytes();
+            self.chunk.extend_from_slice(&buffer[..count]);
+            self.chunk.extend_from_slice(b"\r\n");
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChunkedReader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+
+        let count = output.len().min(self.chunk.len() - self.position);
+        output[..count].copy_from_slice(&self.chunk[self.position..(self.position + count)]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process, str};
+
+    fn serialize(response: Response, head_only: bool) -> String {
+        let mut output = Vec::new();
+        response.with_header("Date", "Sun, 06 Nov 1994 08:49:37 GMT").write_to(&mut output, head_only).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn write_to_fills_in_content_length_date_and_server() {
+        let response = Response::new(Status::Ok)
+            .with_header("Content-Type", "text/plain")
+            .with_body("hello");
+
+        assert_eq!(serialize(response, false),
+                   "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\nServer: strudel\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn write_to_adds_date_when_missing() {
+        let mut output = Vec::new();
+        Response::new(Status::Ok).write_to(&mut output, false).unwrap();
+        let output = str::from_utf8(&output).unwrap();
+
+        assert!(output.contains("\r\nDate: "));
+        assert!(output.ends_with(" GMT\r\nServer: strudel\r\nContent-Length: 0\r\n\r\n"));
+    }
+
+    #[test]
+    fn write_to_leaves_off_body_for_head_requests() {
+        let response = Response::new(Status::Ok).with_body("hello");
+        assert!(serialize(response, true).ends_with("Content-Length: 5\r\n\r\n"));
+    }
+
+    #[test]
+    fn write_to_leaves_off_framing_for_statuses_without_bodies() {
+        let response = Response::new(Status::NoContent).with_header("Allow", "GET");
+        assert_eq!(serialize(response, false), "HTTP/1.1 204 No Content\r\nAllow: GET\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\nServer: strudel\r\n\r\n");
+    }
+
+    #[test]
+    fn write_to_streams_readers_with_chunked_coding() {
+        let reader: BodyReader = Box::new(io::Cursor::new(b"hello world".to_vec()));
+        let response = Response::new(Status::Ok).with_body(Body::Reader(reader));
+
+        assert!(serialize(response, false).ends_with("Transfer-Encoding: chunked\r\n\r\nB\r\nhello world\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn write_to_replaces_framing_headers_set_by_hand() {
+        let response = Response::new(Status::Ok)
+            .with_header("Content-Length", "100")
+            .with_body("hi");
+
+        let output = serialize(response, false);
+        assert!(!output.contains("100"));
+        assert!(output.ends_with("Content-Length: 2\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn into_parts_leaves_files_and_readers_to_be_read() {
+        let reader: BodyReader = Box::new(io::Cursor::new(b"hello".to_vec()));
+        let response = Response::new(Status::Ok).with_body(Body::Reader(reader));
+
+        let (head, body) = response.into_parts(false).unwrap();
+        let mut rest = Vec::new();
+        body.unwrap().read_to_end(&mut rest).unwrap();
+
+        assert!(head.ends_with(b"Transfer-Encoding: chunked\r\n\r\n"));
+        assert_eq!(rest, b"5\r\nhello\r\n0\r\n\r\n");
+        assert!(Response::new(Status::Ok).with_body("hi").into_parts(false).unwrap().1.is_none());
+    }
+
+    #[test]
+    fn into_parts_stops_files_at_their_content_length() {
+        let path = env::temp_dir().join(format!("strudel-response-{}", process::id()));
+        fs::write(&path, "hello").unwrap();
+        let response = Response::new(Status::Ok).with_body(File::open(&path).unwrap());
+
+        let (head, body) = response.into_parts(false).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b" world").unwrap();
+
+        let mut rest = Vec::new();
+        body.unwrap().read_to_end(&mut rest).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(head.ends_with(b"Content-Length: 5\r\n\r\n"));
+        assert_eq!(rest, b"hello");
+    }
+
+    #[test]
+    fn into_parts_fails_files_that_shrink_before_their_content_length() {
+        let path = env::temp_dir().join(format!("strudel-response-shrink-{}", process::id()));
+        fs::write(&path, "hello world").unwrap();
+        let response = Response::new(Status::Ok).with_body(File::open(&path).unwrap());
+
+        let (head, body) = response.into_parts(false).unwrap();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(5).unwrap();
+
+        let mut rest = Vec::new();
+        let result = body.unwrap().read_to_end(&mut rest);
+        fs::remove_file(&path).unwrap();
+
+        assert!(head.ends_with(b"Content-Length: 11\r\n\r\n"));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(rest, b"hello");
+    }
+
+    #[test]
+    fn set_header_replaces_value_regardless_of_case() {
+        let mut response = Response::new(Status::Ok).with_header("Content-Type", "text/plain");
+        response.set_header("content-type", "text/html");
+
+        assert_eq!(response.header("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(response.headers.len(), 1);
+    }
+
+    #[test]
+    fn error_response_uses_reason_as_body() {
+        let response = Response::error(HTTPError::HeaderFieldsTooLarge);
+
+        assert_eq!(response.status.code(), 431);
+        assert!(serialize(response, false).ends_with("\r\n\r\nRequest Header Fields Too Large"));
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fmt;
+use request::{Method, Request};
+use response::Response;
+
+/// Answers the requests for a route.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &Request) -> Response;
+}
+
+impl<F> Handler for F where F: Fn(&Request) -> Response + Send + Sync {
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum RouteError {
+    /// The pattern doesn't start with a slash, has an empty parameter
+    /// name, repeats a name or has segments after a wildcard.
+    InvalidPattern(String),
+    /// Two routes for the same method would match exactly the same
+    /// paths, so one would never be reached.
+    Conflict { method: Method, existing: String, added: String },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteError::InvalidPattern(ref pattern) => write!(formatter, "invalid route pattern {}", pattern),
+            RouteError::Conflict { method, ref existing, ref added } => {
+                write!(formatter, "{} {} conflicts with {} {}", method.as_str(), added, method.as_str(), existing)
+            },
+        }
+    }
+}
+
+/// The result of looking up a request's method and path.
+pub enum Match<'a> {
+    Found(&'a dyn Handler, HashMap<String, String>),
+    /// Routes match the path, but not for this method. Holds the
+    /// methods for the Allow header.
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+/// Matching order within a path segment: a literal beats a parameter,
+/// which beats a wildcard.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+/// Maps methods and path patterns to handlers. Patterns are made of
+/// literal segments, `:name` segments that capture one segment and an
+/// optional final `*name` segment that captures the rest of the path,
+/// as in `/rooms/:id/messages` or `/static/*path`. When several
+/// patterns match, the one with a literal or parameter earliest in the
+/// path wins.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new() }
+    }
+
+    /// Adds a route, or reports why it can't be added. Meant to be
+    /// called while the server starts, so that mistakes surface before
+    /// any request is served.
+    pub fn add<H: Handler + 'static>(&mut self, method: Method, pattern: &str, handler: H) -> Result<(), RouteError> {
+        let segments = parse_pattern(pattern)?;
+
+        if let Some(existing) = self.routes.iter().find(|route| route.method == method && same_shape(&route.segments, &segments)) {
+            return Err(RouteError::Conflict { method, existing: existing.pattern.clone(), added: pattern.to_string() });
+        }
+
+        self.routes.push(Route { method, pattern: pattern.to_string(), segments, handler: Box::new(handler) });
+        Ok(())
+    }
+
+    pub fn get<H: Handler + 'static>(&mut self, pattern: &str, handler: H) -> Result<(), RouteError> {
+        self.add(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler + 'static>(&mut self, pattern: &str, handler: H) -> Result<(), RouteError> {
+        self.add(Method::Post, pattern, handler)
+    }
+
+    /// Finds the handler for a request. HEAD requests fall back to the
+    /// GET route for the path.
+    pub fn find(&self, method: Method, path: &str) -> Match<'_> {
+        let path: Vec<&str> = path.split('/').skip(1).collect();
+
+        let mut matches: Vec<(&Route, HashMap<String, String>)> = self.routes.iter()
+            .filter_map(|route| match_segments(&route.segments, &path).map(|params| (route, params)))
+            .collect();
+
+        if matches.is_empty() {
+            return Match::NotFound;
+        }
+
+        matches.sort_by(|a, b| a.0.segments.cmp(&b.0.segments));
+
+        let found = matches.iter().position(|&(route, _)| route.method == method)
+            .or_else(|| match method {
+                Method::Head => matches.iter().position(|&(route, _)| route.method == Method::Get),
+                _ => None,
+            });
+
+        match found {
+            Some(index) => {
+                let (route, params) = matches.swap_remove(index);
+                Match::Found(route.handler.as_ref(), params)
+            },
+            None => Match::MethodNotAllowed(allowed_methods(matches.iter().map(|&(route, _)| route.method))),
+        }
+    }
+}
+
+/// Lists the methods for an Allow header. Routes for GET also answer
+/// HEAD, and OPTIONS is answered for every path.
+fn allowed_methods<I: Iterator<Item = Method>>(methods: I) -> Vec<Method> {
+    let methods: Vec<Method> = methods.collect();
+
+    Method::ALL.iter()
+        .filter(|method| match **method {
+            Method::Head => methods.contains(&Method::Get) || methods.contains(&Method::Head),
+            Method::Options => true,
+            _ => methods.contains(method),
+        })
+        .cloned()
+        .collect()
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>, RouteError> {
+    let invalid = || RouteError::InvalidPattern(pattern.to_string());
+
+    if !pattern.starts_with('/') {
+        return Err(invalid());
+    }
+
+    let parts: Vec<&str> = pattern.split('/').skip(1).collect();
+    let mut segments = Vec::with_capacity(parts.len());
+    let mut names: Vec<&str> = Vec::new();
+
+    for (index, part) in parts.iter().enumerate() {
+        let segment = if let Some(name) = part.strip_prefix(':') {
+            Segment::Param(name.to_string())
+        } else if let Some(name) = part.strip_prefix('*') {
+            if index != parts.len() - 1 {
+                return Err(invalid());
+            }
+            Segment::Wildcard(name.to_string())
+        } else {
+            Segment::Static(part.to_string())
+        };
+
+        if let Segment::Param(_) | Segment::Wildcard(_) = segment {
+            let name = &part[1..];
+            if name.is_empty() || names.contains(&name) {
+                return Err(invalid());
+            }
+            names.push(name);
+        }
+
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+/// Returns true if the patterns match exactly the same paths, which is
+/// the case when they differ only in the names of what they capture.
+fn same_shape(a: &[Segment], b: &[Segment]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|pair| match pair {
+        (Segment::Static(a), Segment::Static(b)) => a == b,
+        (Segment::Param(_), Segment::Param(_)) => true,
+        (Segment::Wildcard(_), Segment::Wildcard(_)) => true,
+        _ => false,
+    })
+}
+
+fn match_segments(segments: &[Segment], path: &[&str]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+        match *segment {
+            Segment::Static(ref literal) => {
+                if path.get(index) != Some(&literal.as_str()) {
+                    return None;
+                }
+            },
+            Segment::Param(ref name) => match path.get(index) {
+                Some(value) if !value.is_empty() => {
+                    params.insert(name.clone(), value.to_string());
+                },
+                _ => return None,
+            },
+            // The rest of the path, which may be empty.
+            Segment::Wildcard(ref name) => {
+                let rest = if index < path.len() { path[index..].join("/") } else { String::new() };
+                params.insert(name.clone(), rest);
+                return Some(params);
+            },
+        }
+    }
+
+    if path.len() == segments.len() {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use request::parse_request;
+    use response::Status;
+
+    fn respond_with(text: &'static str) -> impl Handler {
+        move |_: &Request| Response::new(Status::Ok).with_body(text)
+    }
+
+    fn find(router: &Router, method: Method, path: &str) -> Option<(String, HashMap<String, String>)> {
+        match router.find(method, path) {
+            Match::Found(handler, params) => {
+                let request = parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+                let mut body = Vec::new();
+                handler.handle(&request).write_to(&mut body, false).unwrap();
+                let body = String::from_utf8(body).unwrap();
+                Some((body.rsplit("\r\n").next().unwrap().to_string(), params))
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn find_matches_literal_paths() {
+        let mut router = Router::new();
+        router.get("/", respond_with("home")).unwrap();
+        router.get("/about", respond_with("about")).unwrap();
+
+        assert_eq!(find(&router, Method::Get, "/").unwrap().0, "home");
+        assert_eq!(find(&router, Method::Get, "/about").unwrap().0, "about");
+        assert!(find(&router, Method::Get, "/about/team").is_none());
+    }
+
+    #[test]
+    fn find_captures_parameters() {
+        let mut router = Router::new();
+        router.get("/rooms/:id/messages/:message", respond_with("message")).unwrap();
+
+        let (_, params) = find(&router, Method::Get, "/rooms/42/messages/7").unwrap();
+
+        assert_eq!(params["id"], "42");
+        assert_eq!(params["message"], "7");
+        assert!(find(&router, Method::Get, "/rooms//messages/7").is_none());
+    }
+
+    #[test]
+    fn find_captures_rest_of_path_with_wildcard() {
+        let mut router = Router::new();
+        router.get("/static/*path", respond_with("static")).unwrap();
+
+        assert_eq!(find(&router, Method::Get, "/static/css/site.css").unwrap().1["path"], "css/site.css");
+        assert_eq!(find(&router, Method::Get, "/static").unwrap().1["path"], "");
+    }
+
+    #[test]
+    fn find_prefers_literals_over_parameters_over_wildcards() {
+        let mut router = Router::new();
+        router.get("/rooms/*rest", respond_with("wildcard")).unwrap();
+        router.get("/rooms/:id", respond_with("param")).unwrap();
+        router.get("/rooms/new", respond_with("literal")).unwrap();
+
+        assert_eq!(find(&router, Method::Get, "/rooms/new").unwrap().0, "literal");
+        assert_eq!(find(&router, Method::Get, "/rooms/42").unwrap().0, "param");
+        assert_eq!(find(&router, Method::Get, "/rooms/42/edit").unwrap().0, "wildcard");
+    }
+
+    #[test]
+    fn find_matches_on_method() {
+        let mut router = Router::new();
+        router.get("/rooms", respond_with("list")).unwrap();
+        router.post("/rooms", respond_with("create")).unwrap();
+
+        assert_eq!(find(&router, Method::Get, "/rooms").unwrap().0, "list");
+        assert_eq!(find(&router, Method::Post, "/rooms").unwrap().0, "create");
+        assert_eq!(find(&router, Method::Head, "/rooms").unwrap().0, "list");
+    }
+
+    #[test]
+    fn find_lists_allowed_methods_when_method_doesnt_match() {
+        let mut router = Router::new();
+        router.get("/rooms/:id", respond_with("show")).unwrap();
+        router.add(Method::Delete, "/rooms/:id", respond_with("delete")).unwrap();
+
+        match router.find(Method::Post, "/rooms/42") {
+            Match::MethodNotAllowed(methods) => {
+                assert_eq!(methods, vec![Method::Get, Method::Head, Method::Delete, Method::Options]);
+            },
+            _ => panic!("expected MethodNotAllowed"),
+        }
+
+        assert!(matches!(router.find(Method::Get, "/missing"), Match::NotFound));
+    }
+
+    #[test]
+    fn add_reports_conflicting_routes() {
+        let mut router = Router::new();
+        router.get("/rooms/:id", respond_with("first")).unwrap();
+
+        assert_eq!(router.get("/rooms/:name", respond_with("second")),
+                   Err(RouteError::Conflict { method: Method::Get, existing: "/rooms/:id".to_string(), added: "/rooms/:name".to_string() }));
+        assert!(router.post("/rooms/:name", respond_with("other method")).is_ok());
+        assert!(router.get("/rooms/new", respond_with("literal")).is_ok());
+    }
+
+    #[test]
+    fn add_rejects_invalid_patterns() {
+        let mut router = Router::new();
+
+        for pattern in &["rooms", "/rooms/:", "/rooms/:id/:id", "/static/*path/more", "/static/*"] {
+            assert_eq!(router.get(pattern, respond_with("invalid")), Err(RouteError::InvalidPattern(pattern.to_string())));
+        }
+    }
+}
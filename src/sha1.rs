@@ -1,7 +1,7 @@
+use digest::{BlockBuffer, Digest};
+
 pub struct SHA1Context {
-    input_buffer: [u8; 64],
-    input_index: usize,
-    length: u64,
+    buffer: BlockBuffer<64>,
     h: [u32; 5],
 }
 
@@ -13,89 +13,57 @@ const H_INIT: [u32; 5] = [
     0xC3D2E1F0,
 ];
 
+impl Default for SHA1Context {
+    fn default() -> SHA1Context {
+        SHA1Context::new()
+    }
+}
+
 impl SHA1Context {
     pub fn new() -> SHA1Context {
-        SHA1Context { input_buffer: [0; 64], input_index: 0, length: 0, h: H_INIT }
+        SHA1Context { buffer: BlockBuffer::new(), h: H_INIT }
     }
 
-    pub fn add(&mut self, mut new_input: &[u8]) {
-        self.length += new_input.len() as u64;
-        let mut bytes_til_full_block = self.input_buffer.len() - self.input_index;
-
-        while new_input.len() >= bytes_til_full_block {
-            let filler: &[u8] = &new_input[..bytes_til_full_block];
-
-            for (offset, byte) in filler.iter().enumerate() {
-                self.input_buffer[self.input_index + offset] = *byte;
-            }
+    /// The same as `Digest::update`.
+    pub fn add(&mut self, input: &[u8]) {
+        self.update(input);
+    }
 
-            self.h = process_message_block(self.input_buffer, self.h);
+    /// The same as `Digest::finalize`.
+    pub fn digest(&mut self) -> [u8; 20] {
+        self.finalize()
+    }
+}
 
-            self.input_index = 0;
-            new_input = &new_input[bytes_til_full_block..];
-            bytes_til_full_block = self.input_buffer.len();
-        }
+impl Digest for SHA1Context {
+    type Output = [u8; 20];
 
-        for (offset, byte) in new_input.iter().enumerate() {
-            self.input_buffer[self.input_index + offset] = *byte;
-        }
+    const BLOCK_SIZE: usize = 64;
+    const OUTPUT_SIZE: usize = 20;
 
-        self.input_index += new_input.len();
+    fn update(&mut self, input: &[u8]) {
+        let h = &mut self.h;
+        self.buffer.update(input, |block| *h = process_message_block(*block, *h));
     }
 
-    pub fn digest(&mut self) -> [u8; 20] {
-        self.input_buffer[self.input_index] = 0x80;
-
-        for index in (self.input_index + 1)..self.input_buffer.len() {
-            self.input_buffer[index] = 0;
-        }
-
-        if self.input_index > 55 {
-            self.h = process_message_block(self.input_buffer, self.h);
-            self.input_buffer = [0; 64];
+    fn finalize(&mut self) -> [u8; 20] {
+        {
+            let h = &mut self.h;
+            self.buffer.finish(8, |block| *h = process_message_block(*block, *h));
         }
 
-        for (offset, byte) in self.length_bytes().iter().enumerate() {
-            self.input_buffer[56 + offset] = *byte;
+        let mut digest = [0; 20];
+        for (bytes, word) in digest.chunks_mut(4).zip(self.h.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
         }
 
-        self.h = process_message_block(self.input_buffer, self.h);
-
-        [
-            (self.h[0] >> 24) as u8,
-            (self.h[0] >> 16) as u8,
-            (self.h[0] >> 8) as u8,
-            self.h[0] as u8,
-            (self.h[1] >> 24) as u8,
-            (self.h[1] >> 16) as u8,
-            (self.h[1] >> 8) as u8,
-            self.h[1] as u8,
-            (self.h[2] >> 24) as u8,
-            (self.h[2] >> 16) as u8,
-            (self.h[2] >> 8) as u8,
-            self.h[2] as u8,
-            (self.h[3] >> 24) as u8,
-            (self.h[3] >> 16) as u8,
-            (self.h[3] >> 8) as u8,
-            self.h[3] as u8,
-            (self.h[4] >> 24) as u8,
-            (self.h[4] >> 16) as u8,
-            (self.h[4] >> 8) as u8,
-            self.h[4] as u8,
-        ]
+        self.h = H_INIT;
+        digest
     }
 
-    fn length_bytes(&self) -> [u8; 8] {
-        let length_in_bits = self.length * 8;
-
-        [(length_in_bits >> 56) as u8,
-         (length_in_bits >> 48) as u8,
-         (length_in_bits >> 40) as u8,
-         (length_in_bits >> 32) as u8,
-         (length_in_bits >> 24) as u8,
-         (length_in_bits >> 16) as u8,
-         (length_in_bits >> 8) as u8,
-         length_in_bits as u8]
+    fn reset(&mut self) {
+        self.buffer.reset();
+        self.h = H_INIT;
     }
 }
 
@@ -135,8 +103,8 @@ fn process_message_block(input: [u8; 64], mut h: [u32; 5]) -> [u32; 5] {
     d = h[3];
     e = h[4];
 
-    for t in 0..80 {
-        temp = a.rotate_left(5).wrapping_add(f(t, b, c, d)).wrapping_add(e).wrapping_add(w[t]).wrapping_add(k(t));
+    for (t, word) in w.iter().enumerate() {
+        temp = a.rotate_left(5).wrapping_add(f(t, b, c, d)).wrapping_add(e).wrapping_add(*word).wrapping_add(k(t));
         e = d;
         d = c;
         c = b.rotate_left(30);
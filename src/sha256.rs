@@ -0,0 +1,162 @@
+use digest::{BlockBuffer, Digest};
+
+/// SHA-256 from Section 6.2 of FIPS 180-4.
+pub struct SHA256Context {
+    buffer: BlockBuffer<64>,
+    h: [u32; 8],
+}
+
+const H_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+    0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The first 32 bits of the fractional parts of the cube roots of the
+/// first 64 primes, from Section 4.2.2 of FIPS 180-4.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Default for SHA256Context {
+    fn default() -> SHA256Context {
+        SHA256Context::new()
+    }
+}
+
+impl SHA256Context {
+    pub fn new() -> SHA256Context {
+        SHA256Context { buffer: BlockBuffer::new(), h: H_INIT }
+    }
+}
+
+impl Digest for SHA256Context {
+    type Output = [u8; 32];
+
+    const BLOCK_SIZE: usize = 64;
+    const OUTPUT_SIZE: usize = 32;
+
+    fn update(&mut self, input: &[u8]) {
+        let h = &mut self.h;
+        self.buffer.update(input, |block| process_message_block(block, h));
+    }
+
+    fn finalize(&mut self) -> [u8; 32] {
+        {
+            let h = &mut self.h;
+            self.buffer.finish(8, |block| process_message_block(block, h));
+        }
+
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_mut(4).zip(self.h.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+
+        self.h = H_INIT;
+        digest
+    }
+
+    fn reset(&mut self) {
+        self.buffer.reset();
+        self.h = H_INIT;
+    }
+}
+
+fn process_message_block(input: &[u8; 64], h: &mut [u32; 8]) {
+    let mut w = [0u32; 64];
+
+    for (index, chunk) in input.chunks(4).enumerate() {
+        w[index] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    for t in 16..64 {
+        let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+        let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+        w[t] = s1.wrapping_add(w[t - 7]).wrapping_add(s0).wrapping_add(w[t - 16]);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = *h;
+
+    for t in 0..64 {
+        let sigma1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = hh.wrapping_add(sigma1).wrapping_add(ch).wrapping_add(K[t]).wrapping_add(w[t]);
+        let sigma0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = sigma0.wrapping_add(maj);
+
+        hh = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+
+    for (word, value) in h.iter_mut().zip(&[a, b, c, d, e, f, g, hh]) {
+        *word = word.wrapping_add(*value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha256_digest_computes_digest() {
+        assert_eq!(hex(&SHA256Context::hash(b"abc")),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn sha256_digest_of_empty_input() {
+        assert_eq!(hex(&SHA256Context::hash(b"")),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn sha256_digest_processes_multiple_512_bit_blocks() {
+        // 448 bits, which leaves no room in the first block for the
+        // padding and length.
+        let input = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+
+        assert_eq!(hex(&SHA256Context::hash(input)),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    }
+
+    #[test]
+    fn sha256_digest_of_one_million_a() {
+        let mut context = SHA256Context::new();
+        for _ in 0..1000 {
+            context.update(&[b'a'; 1000]);
+        }
+
+        assert_eq!(hex(&context.finalize()),
+                   "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn sha256_finalize_resets_context() {
+        let mut context = SHA256Context::new();
+        context.update(b"abc");
+        context.finalize();
+        context.update(b"abc");
+        assert_eq!(context.finalize(), SHA256Context::hash(b"abc"));
+
+        context.update(b"discarded");
+        context.reset();
+        assert_eq!(context.finalize(), SHA256Context::hash(b""));
+    }
+}
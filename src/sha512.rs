@@ -0,0 +1,182 @@
+use digest::{BlockBuffer, Digest};
+
+/// SHA-512 from Section 6.4 of FIPS 180-4.
+pub struct SHA512Context {
+    buffer: BlockBuffer<128>,
+    h: [u64; 8],
+}
+
+const H_INIT: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+/// The first 64 bits of the fractional parts of the cube roots of the
+/// first 80 primes, from Section 4.2.3 of FIPS 180-4.
+const K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+impl Default for SHA512Context {
+    fn default() -> SHA512Context {
+        SHA512Context::new()
+    }
+}
+
+impl SHA512Context {
+    pub fn new() -> SHA512Context {
+        SHA512Context { buffer: BlockBuffer::new(), h: H_INIT }
+    }
+}
+
+impl Digest for SHA512Context {
+    type Output = [u8; 64];
+
+    const BLOCK_SIZE: usize = 128;
+    const OUTPUT_SIZE: usize = 64;
+
+    fn update(&mut self, input: &[u8]) {
+        let h = &mut self.h;
+        self.buffer.update(input, |block| process_message_block(block, h));
+    }
+
+    /// SHA-512 ends its padding with a 128-bit length, rather than the
+    /// 64 bits SHA-1 and SHA-256 use.
+    fn finalize(&mut self) -> [u8; 64] {
+        {
+            let h = &mut self.h;
+            self.buffer.finish(16, |block| process_message_block(block, h));
+        }
+
+        let mut digest = [0; 64];
+        for (bytes, word) in digest.chunks_mut(8).zip(self.h.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+
+        self.h = H_INIT;
+        digest
+    }
+
+    fn reset(&mut self) {
+        self.buffer.reset();
+        self.h = H_INIT;
+    }
+}
+
+fn process_message_block(input: &[u8; 128], h: &mut [u64; 8]) {
+    let mut w = [0u64; 80];
+
+    for (index, chunk) in input.chunks(8).enumerate() {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(chunk);
+        w[index] = u64::from_be_bytes(bytes);
+    }
+
+    for t in 16..80 {
+        let s0 = w[t - 15].rotate_right(1) ^ w[t - 15].rotate_right(8) ^ (w[t - 15] >> 7);
+        let s1 = w[t - 2].rotate_right(19) ^ w[t - 2].rotate_right(61) ^ (w[t - 2] >> 6);
+        w[t] = s1.wrapping_add(w[t - 7]).wrapping_add(s0).wrapping_add(w[t - 16]);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = *h;
+
+    for t in 0..80 {
+        let sigma1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = hh.wrapping_add(sigma1).wrapping_add(ch).wrapping_add(K[t]).wrapping_add(w[t]);
+        let sigma0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = sigma0.wrapping_add(maj);
+
+        hh = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+
+    for (word, value) in h.iter_mut().zip(&[a, b, c, d, e, f, g, hh]) {
+        *word = word.wrapping_add(*value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha512_digest_computes_digest() {
+        assert_eq!(hex(&SHA512Context::hash(b"abc")),
+                   "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                    2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f");
+    }
+
+    #[test]
+    fn sha512_digest_of_empty_input() {
+        assert_eq!(hex(&SHA512Context::hash(b"")),
+                   "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                    47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e");
+    }
+
+    #[test]
+    fn sha512_digest_processes_multiple_1024_bit_blocks() {
+        // 896 bits, which leaves no room in the first block for the
+        // padding and length.
+        let input = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmno\
+                      ijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+        assert_eq!(hex(&SHA512Context::hash(input)),
+                   "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+                    501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909");
+    }
+
+    #[test]
+    fn sha512_digest_of_one_million_a() {
+        let mut context = SHA512Context::new();
+        for _ in 0..1000 {
+            context.update(&[b'a'; 1000]);
+        }
+
+        assert_eq!(hex(&context.finalize()),
+                   "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973eb\
+                    de0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b");
+    }
+
+    #[test]
+    fn sha512_digest_can_accept_input_in_chunks() {
+        let input = [b'a'; 300];
+        let mut context = SHA512Context::new();
+
+        for chunk in input.chunks(7) {
+            context.update(chunk);
+        }
+
+        assert_eq!(context.finalize(), SHA512Context::hash(&input));
+    }
+}
@@ -0,0 +1,95 @@
+use std::io;
+use std::os::raw::{c_int, c_void};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+pub const SIGINT: c_int = 2;
+pub const SIGTERM: c_int = 15;
+
+/// `SIG_ERR` from <signal.h>.
+const SIGNAL_ERROR: usize = !0;
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    fn pipe(fds: *mut c_int) -> c_int;
+    fn read(fd: c_int, buffer: *mut c_void, count: usize) -> isize;
+    fn write(fd: c_int, buffer: *const c_void, count: usize) -> isize;
+}
+
+// The two ends of the pipe the handler reports signals through, since
+// writing to a pipe is one of the few things a handler may safely do.
+static READ_FD: AtomicI32 = AtomicI32::new(-1);
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signum: c_int) {
+    let byte = signum as u8;
+
+    unsafe {
+        write(WRITE_FD.load(Ordering::SeqCst), &byte as *const u8 as *const c_void, 1);
+    }
+}
+
+/// Stops SIGTERM and SIGINT from killing the process. From now on they
+/// are queued up for `wait` instead, so the server can shut down in
+/// its own time.
+pub fn catch_termination() -> io::Result<()> {
+    if READ_FD.load(Ordering::SeqCst) < 0 {
+        let mut fds = [0; 2];
+
+        if unsafe { pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        WRITE_FD.store(fds[1], Ordering::SeqCst);
+        READ_FD.store(fds[0], Ordering::SeqCst);
+    }
+
+    for signum in &[SIGTERM, SIGINT] {
+        if unsafe { signal(*signum, on_signal) } == SIGNAL_ERROR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Blocks until SIGTERM or SIGINT arrives, returning which one it was.
+/// `catch_termination` must have been called first.
+pub fn wait() -> io::Result<c_int> {
+    let fd = READ_FD.load(Ordering::SeqCst);
+    assert!(fd >= 0, "signal::catch_termination hasn't been called");
+
+    let mut byte = 0u8;
+
+    loop {
+        match unsafe { read(fd, &mut byte as *mut u8 as *mut c_void, 1) } {
+            1 => return Ok(c_int::from(byte)),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "signal pipe closed")),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    #[test]
+    fn wait_returns_signal_once_caught() {
+        catch_termination().unwrap();
+
+        unsafe {
+            raise(SIGTERM);
+        }
+
+        assert_eq!(wait().unwrap(), SIGTERM);
+    }
+}
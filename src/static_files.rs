@@ -0,0 +1,295 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use date::format_http_date;
+use request::{HTTPError, Request};
+use response::{Response, Status};
+use router::Handler;
+
+const INDEX_FILE: &str = "index.html";
+
+/// `ENOTDIR` from <errno.h>, the same on Linux and macOS.
+const ENOTDIR: i32 = 20;
+
+/// Serves the files under a root directory. Mount it on a route ending
+/// in a `*path` wildcard, such as `/static/*path`, and the captured
+/// path is looked up under the root, with `index.html` served for a
+/// directory. Files are streamed rather than read into memory.
+///
+/// Responses carry a Last-Modified header from the file's modification
+/// time and an ETag made from its size and modification time. Hashing
+/// the contents instead would mean reading the whole file before
+/// answering, which on the event loop holds up every other connection.
+///
+/// Requests can't reach outside the root, whether with `..` segments
+/// or through a symbolic link that points elsewhere.
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    /// Fails if the root directory doesn't exist.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<StaticFiles> {
+        let root = fs::canonicalize(root)?;
+
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "static file root isn't a directory"));
+        }
+
+        Ok(StaticFiles { root })
+    }
+
+    /// Finds the file a wildcard path refers to.
+    fn resolve(&self, path: &str) -> Result<PathBuf, HTTPError> {
+        let relative = Path::new(path);
+
+        // The router hands over a normalized path, but a handler
+        // shouldn't rely on that to keep requests inside the root.
+        if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(HTTPError::Forbidden);
+        }
+
+        let mut resolved = fs::canonicalize(self.root.join(relative)).map_err(from_io_error)?;
+
+        if !resolved.starts_with(&self.root) {
+            return Err(HTTPError::Forbidden);
+        }
+
+        if resolved.is_dir() {
+            resolved = fs::canonicalize(resolved.join(INDEX_FILE)).map_err(from_io_error)?;
+
+            if !resolved.starts_with(&self.root) {
+                return Err(HTTPError::Forbidden);
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    fn serve(&self, request: &Request) -> Result<Response, HTTPError> {
+        let path = self.resolve(request.param("path").unwrap_or(""))?;
+        let file = File::open(&path).map_err(from_io_error)?;
+        let metadata = file.metadata().map_err(from_io_error)?;
+
+        if !metadata.is_file() {
+            return Err(HTTPError::NotFound);
+        }
+
+        let mut response = Response::new(Status::Ok).with_header("Content-Type", content_type(&path));
+
+        if let Ok(modified) = metadata.modified() {
+            response.set_header("Last-Modified", &format_http_date(modified));
+            response.set_header("ETag", &file_etag(metadata.len(), modified));
+        }
+
+        Ok(response.with_body(file))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request) -> Response {
+        match self.serve(request) {
+            Ok(response) => response,
+            Err(error) => Response::error(error),
+        }
+    }
+}
+
+/// A strong entity tag from a file's length and modification time, in
+/// hex as `"length-seconds.nanoseconds"`.
+fn file_etag(length: u64, modified: SystemTime) -> String {
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("\"{:x}-{:x}.{:x}\"", length, since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+fn from_io_error(error: io::Error) -> HTTPError {
+    match error.kind() {
+        io::ErrorKind::NotFound => HTTPError::NotFound,
+        io::ErrorKind::PermissionDenied => HTTPError::Forbidden,
+        // Looking for a file beneath something that isn't a directory.
+        _ if error.raw_os_error() == Some(ENOTDIR) => HTTPError::NotFound,
+        _ => HTTPError::InternalServerError,
+    }
+}
+
+/// Picks a media type from a file's extension, falling back to
+/// application/octet-stream for any it doesn't know.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return "application/octet-stream",
+    };
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html;charset=utf-8",
+        "css" => "text/css;charset=utf-8",
+        "js" | "mjs" => "text/javascript;charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain;charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::prelude::*;
+    use std::ops::Deref;
+    use std::process;
+    use request::parse_request;
+    use router::{Match, Router};
+
+    /// A directory made for one test, deleted when the test ends.
+    struct Fixture(PathBuf);
+
+    impl Deref for Fixture {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Makes a fresh directory with a public root inside it and a
+    /// secret file next to the root.
+    fn fixture(name: &str) -> Fixture {
+        let base = env::temp_dir().join(format!("strudel-static-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("public/docs")).unwrap();
+        fs::write(base.join("public/site.css"), "body {}").unwrap();
+        fs::write(base.join("public/docs/index.html"), "<h1>Docs</h1>").unwrap();
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+        Fixture(base)
+    }
+
+    /// Answers a GET for the target the way the server would with the
+    /// handler mounted at `/static/*path`.
+    fn get(files: &StaticFiles, target: &str) -> Response {
+        let mut router = Router::new();
+        router.get("/static/*path", |_: &Request| Response::new(Status::Ok)).unwrap();
+
+        let input = format!("GET {} HTTP/1.1\r\n\r\n", target);
+        let mut request = parse_request(input.as_bytes()).unwrap();
+
+        match router.find(request.method, request.path()) {
+            Match::Found(_, params) => request.params = params,
+            _ => panic!("no route for {}", target),
+        }
+
+        files.handle(&request)
+    }
+
+    fn body(response: Response) -> String {
+        let mut text = String::new();
+        if let (_, Some(mut body)) = response.into_parts(false).unwrap() {
+            body.read_to_string(&mut text).unwrap();
+        }
+        text
+    }
+
+    #[test]
+    fn handle_serves_files_with_content_type() {
+        let base = fixture("serve");
+        let response = get(&StaticFiles::new(base.join("public")).unwrap(), "/static/site.css");
+
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(response.header("Content-Type"), Some("text/css;charset=utf-8"));
+        assert_eq!(body(response), "body {}");
+    }
+
+    #[test]
+    fn handle_serves_index_for_directories() {
+        let base = fixture("index");
+        let response = get(&StaticFiles::new(base.join("public")).unwrap(), "/static/docs/");
+
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(response.header("Content-Type"), Some("text/html;charset=utf-8"));
+        assert_eq!(body(response), "<h1>Docs</h1>");
+    }
+
+    #[test]
+    fn handle_returns_404_for_missing_files() {
+        let base = fixture("missing");
+
+        let files = StaticFiles::new(base.join("public")).unwrap();
+
+        assert_eq!(get(&files, "/static/missing.js").status, Status::NotFound);
+        assert_eq!(get(&files, "/static/site.css/more").status, Status::NotFound);
+        assert_eq!(get(&files, "/static/").status, Status::NotFound);
+    }
+
+    #[test]
+    fn resolve_refuses_paths_outside_root() {
+        let base = fixture("traversal");
+        let files = StaticFiles::new(base.join("public")).unwrap();
+
+        assert_eq!(files.resolve("../secret.txt"), Err(HTTPError::Forbidden));
+        assert_eq!(files.resolve("/etc/passwd"), Err(HTTPError::Forbidden));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn handle_returns_403_for_symlinks_out_of_root() {
+        use std::os::unix::fs::symlink;
+
+        let base = fixture("symlink");
+        symlink(base.join("secret.txt"), base.join("public/leak.txt")).unwrap();
+        symlink(base.join("public/site.css"), base.join("public/alias.css")).unwrap();
+
+        let files = StaticFiles::new(base.join("public")).unwrap();
+
+        assert_eq!(get(&files, "/static/leak.txt").status, Status::Forbidden);
+        assert_eq!(get(&files, "/static/alias.css").status, Status::Ok);
+    }
+
+    #[test]
+    fn handle_sets_etag_and_last_modified() {
+        let base = fixture("etag");
+        let files = StaticFiles::new(base.join("public")).unwrap();
+        let response = get(&files, "/static/site.css");
+
+        let modified = fs::metadata(base.join("public/site.css")).unwrap().modified().unwrap();
+        let etag = response.header("ETag").unwrap().to_string();
+
+        assert_eq!(etag, file_etag(7, modified));
+        assert!(etag.starts_with("\"7-"));
+        assert!(response.header("Last-Modified").unwrap().ends_with(" GMT"));
+        assert_eq!(body(response), "body {}");
+
+        // Changing the file changes its length, and so its ETag.
+        fs::write(base.join("public/site.css"), "body { margin: 0 }").unwrap();
+        assert_ne!(get(&files, "/static/site.css").header("ETag"), Some(etag.as_str()));
+    }
+
+    #[test]
+    fn content_type_falls_back_to_octet_stream() {
+        assert_eq!(content_type(Path::new("logo.PNG")), "image/png");
+        assert_eq!(content_type(Path::new("archive.tar.xz")), "application/octet-stream");
+        assert_eq!(content_type(Path::new("README")), "application/octet-stream");
+    }
+}
@@ -0,0 +1,229 @@
+use std::slice;
+use request::HTTPError;
+
+/// Decodes the `%XX` escapes from Section 2.1 of RFC 3986. With
+/// `plus_as_space`, a `+` is read as a space, as it is in the
+/// application/x-www-form-urlencoded format used for query strings.
+/// Returns None if a `%` isn't followed by two hex digits.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    let mut output = Vec::with_capacity(input.len());
+    let mut index = 0;
+
+    while index < input.len() {
+        match input[index] {
+            b'%' => {
+                let high = input.get(index + 1).and_then(|byte| hex_value(*byte))?;
+                let low = input.get(index + 2).and_then(|byte| hex_value(*byte))?;
+                output.push(high << 4 | low);
+                index += 3;
+            },
+            b'+' if plus_as_space => {
+                output.push(b' ');
+                index += 1;
+            },
+            byte => {
+                output.push(byte);
+                index += 1;
+            },
+        }
+    }
+
+    Some(output)
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes the path of a request target and removes its `.` and `..`
+/// segments as in Section 5.2.4 of RFC 3986, so `/a/./b/../c` becomes
+/// `/a/c`. Dot segments are removed after decoding, so `%2E%2E` can't
+/// be used to climb out of a directory either. A segment holding an
+/// encoded slash or NUL, or that isn't UTF-8 once decoded, is rejected,
+/// as is a path that doesn't start with a slash. The asterisk form
+/// used by `OPTIONS *` is returned as it is.
+pub fn decode_path(path: &str) -> Result<String, HTTPError> {
+    if path == "*" {
+        return Ok(path.to_string());
+    }
+
+    if !path.starts_with('/') {
+        return Err(HTTPError::BadRequest);
+    }
+
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+    let mut segments: Vec<String> = Vec::with_capacity(parts.len());
+
+    for (index, part) in parts.iter().enumerate() {
+        let bytes = percent_decode(part, false).ok_or(HTTPError::BadRequest)?;
+
+        if bytes.contains(&b'/') || bytes.contains(&0) {
+            return Err(HTTPError::BadRequest);
+        }
+
+        let segment = String::from_utf8(bytes).map_err(|_| HTTPError::BadRequest)?;
+        let last = index == parts.len() - 1;
+
+        match segment.as_str() {
+            "." => {},
+            ".." => {
+                segments.pop();
+            },
+            _ => {
+                segments.push(segment);
+                continue;
+            },
+        }
+
+        // A dot segment at the end leaves the path pointing at a
+        // directory, so it keeps its trailing slash.
+        if last {
+            segments.push(String::new());
+        }
+    }
+
+    Ok(format!("/{}", segments.join("/")))
+}
+
+/// The name/value pairs of a query string such as `room=1&tag=a&tag=b`.
+/// A name may appear more than once, and the pairs keep the order they
+/// were sent in.
+#[derive(PartialEq, Debug, Default)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    /// Parses a query string in the application/x-www-form-urlencoded
+    /// format. A pair without an `=` has an empty value. Returns 400 if
+    /// an escape is malformed or doesn't decode to UTF-8.
+    pub fn parse(query: &str) -> Result<Query, HTTPError> {
+        let mut pairs = Vec::new();
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let name = decode_component(parts.next().unwrap_or(""))?;
+            let value = decode_component(parts.next().unwrap_or(""))?;
+            pairs.push((name, value));
+        }
+
+        Ok(Query { pairs })
+    }
+
+    /// The first value sent for a name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter()
+            .find(|pair| pair.0 == name)
+            .map(|pair| pair.1.as_str())
+    }
+
+    /// Every value sent for a name, in order.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.pairs.iter()
+            .filter(|pair| pair.0 == name)
+            .map(|pair| pair.1.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pairs.iter().any(|pair| pair.0 == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (String, String)> {
+        self.pairs.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Query {
+    type Item = &'a (String, String);
+    type IntoIter = slice::Iter<'a, (String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+fn decode_component(component: &str) -> Result<String, HTTPError> {
+    let bytes = percent_decode(component, true).ok_or(HTTPError::BadRequest)?;
+    String::from_utf8(bytes).map_err(|_| HTTPError::BadRequest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_decodes_escapes() {
+        assert_eq!(percent_decode("caf%C3%A9", false), Some("café".as_bytes().to_vec()));
+        assert_eq!(percent_decode("100%25", false), Some(b"100%".to_vec()));
+        assert_eq!(percent_decode("a+b", false), Some(b"a+b".to_vec()));
+        assert_eq!(percent_decode("a+b", true), Some(b"a b".to_vec()));
+    }
+
+    #[test]
+    fn percent_decode_rejects_malformed_escapes() {
+        for input in &["%", "%4", "%G0", "abc%2"] {
+            assert_eq!(percent_decode(input, false), None);
+        }
+    }
+
+    #[test]
+    fn decode_path_decodes_segments() {
+        assert_eq!(decode_path("/caf%C3%A9"), Ok("/café".to_string()));
+        assert_eq!(decode_path("/rooms/a%20b/"), Ok("/rooms/a b/".to_string()));
+        assert_eq!(decode_path("/"), Ok("/".to_string()));
+        assert_eq!(decode_path("*"), Ok("*".to_string()));
+    }
+
+    #[test]
+    fn decode_path_removes_dot_segments() {
+        assert_eq!(decode_path("/a/./b/../c"), Ok("/a/c".to_string()));
+        assert_eq!(decode_path("/a/b/.."), Ok("/a/".to_string()));
+        assert_eq!(decode_path("/../../etc/passwd"), Ok("/etc/passwd".to_string()));
+        assert_eq!(decode_path("/static/%2E%2E/%2e%2e/secret"), Ok("/secret".to_string()));
+    }
+
+    #[test]
+    fn decode_path_rejects_encoded_slash_and_nul() {
+        for path in &["/a%2Fb", "/a%2fb", "/a%00b", "/%C3", "/bad%zz", "relative"] {
+            assert_eq!(decode_path(path), Err(HTTPError::BadRequest));
+        }
+    }
+
+    #[test]
+    fn query_parse_keeps_repeated_names_in_order() {
+        let query = Query::parse("tag=a&room=1&tag=b&flag").unwrap();
+
+        assert_eq!(query.get("tag"), Some("a"));
+        assert_eq!(query.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(query.get("room"), Some("1"));
+        assert_eq!(query.get("flag"), Some(""));
+        assert!(query.contains("flag"));
+        assert_eq!(query.get("missing"), None);
+    }
+
+    #[test]
+    fn query_parse_decodes_names_and_values() {
+        let query = Query::parse("full+name=Ada%20Lovelace&q=1%2B1%3D2&&").unwrap();
+
+        assert_eq!(query.get("full name"), Some("Ada Lovelace"));
+        assert_eq!(query.get("q"), Some("1+1=2"));
+        assert_eq!(query.iter().count(), 2);
+    }
+
+    #[test]
+    fn query_parse_rejects_malformed_escapes() {
+        assert_eq!(Query::parse("q=%ZZ"), Err(HTTPError::BadRequest));
+        assert_eq!(Query::parse("q=%FF"), Err(HTTPError::BadRequest));
+    }
+}
@@ -0,0 +1,122 @@
+/// Validates UTF-8 a piece at a time, so a multi-byte character may be
+/// split across calls to `feed`. The accepted byte ranges follow
+/// Table 3-7 of the Unicode Standard, which rules out overlong
+/// encodings, surrogates and code points above U+10FFFF.
+pub struct Utf8Validator {
+    remaining: u8,
+    lower: u8,
+    upper: u8,
+}
+
+impl Default for Utf8Validator {
+    fn default() -> Utf8Validator {
+        Utf8Validator::new()
+    }
+}
+
+impl Utf8Validator {
+    pub fn new() -> Utf8Validator {
+        Utf8Validator { remaining: 0, lower: 0x80, upper: 0xBF }
+    }
+
+    /// Checks the next chunk of input. Returns false as soon as a byte
+    /// is found that can't be part of valid UTF-8, no matter what
+    /// follows it.
+    pub fn feed(&mut self, input: &[u8]) -> bool {
+        for byte in input {
+            let byte = *byte;
+
+            if self.remaining > 0 {
+                if byte < self.lower || byte > self.upper {
+                    return false;
+                }
+
+                self.remaining -= 1;
+                self.lower = 0x80;
+                self.upper = 0xBF;
+                continue;
+            }
+
+            let (remaining, lower, upper) = match byte {
+                0x00..=0x7F => (0, 0x80, 0xBF),
+                0xC2..=0xDF => (1, 0x80, 0xBF),
+                0xE0 => (2, 0xA0, 0xBF),
+                0xE1..=0xEC | 0xEE..=0xEF => (2, 0x80, 0xBF),
+                0xED => (2, 0x80, 0x9F),
+                0xF0 => (3, 0x90, 0xBF),
+                0xF1..=0xF3 => (3, 0x80, 0xBF),
+                0xF4 => (3, 0x80, 0x8F),
+                _ => return false,
+            };
+
+            self.remaining = remaining;
+            self.lower = lower;
+            self.upper = upper;
+        }
+
+        true
+    }
+
+    /// Returns true if the input so far doesn't end partway through a
+    /// character.
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(input: &[u8]) -> bool {
+        let mut validator = Utf8Validator::new();
+        validator.feed(input) && validator.is_complete()
+    }
+
+    #[test]
+    fn validator_accepts_ascii_and_multi_byte_characters() {
+        assert!(validate(b""));
+        assert!(validate(b"Hello"));
+        assert!(validate("κόσμε".as_bytes()));
+        assert!(validate("\u{FFFF}\u{10000}\u{10FFFF}".as_bytes()));
+    }
+
+    #[test]
+    fn validator_accepts_characters_split_across_chunks() {
+        let input = "Hello-µ@ßöäüàá-UTF-8!!".as_bytes();
+
+        for split in 0..input.len() {
+            let mut validator = Utf8Validator::new();
+            assert!(validator.feed(&input[..split]));
+            assert!(validator.feed(&input[split..]));
+            assert!(validator.is_complete());
+        }
+    }
+
+    #[test]
+    fn validator_rejects_overlong_encodings() {
+        assert!(!validate(&[0xC0, 0xAF]));
+        assert!(!validate(&[0xE0, 0x80, 0xAF]));
+        assert!(!validate(&[0xF0, 0x80, 0x80, 0xAF]));
+    }
+
+    #[test]
+    fn validator_rejects_surrogates_and_code_points_above_max() {
+        assert!(!validate(&[0xED, 0xA0, 0x80]));
+        assert!(!validate(&[0xF4, 0x90, 0x80, 0x80]));
+        assert!(!validate(&[0xF5, 0x80, 0x80, 0x80]));
+    }
+
+    #[test]
+    fn validator_rejects_invalid_byte_before_input_is_complete() {
+        let mut validator = Utf8Validator::new();
+        assert!(!validator.feed(&[0xCE, 0xBA, 0xE1, 0xBD, 0xB9, 0xCF, 0x83, 0xCE, 0xBC, 0xCE, 0xB5, 0xED, 0xA0]));
+    }
+
+    #[test]
+    fn validator_is_incomplete_when_input_ends_mid_character() {
+        let mut validator = Utf8Validator::new();
+        assert!(validator.feed(&[0xE2, 0x82]));
+        assert!(!validator.is_complete());
+    }
+}
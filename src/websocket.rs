@@ -0,0 +1,1396 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpStream};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use utf8::Utf8Validator;
+use hub::Hub;
+use permessage_deflate::{Compressor, Decompressor, DeflateParams};
+use request::{HTTPError, Request};
+
+/// The largest payload a single frame may carry. The length field
+/// allows up to 2^63 bytes, so without a cap a client could make us
+/// allocate an arbitrary amount of memory.
+const MAX_PAYLOAD_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// The largest message that may be assembled from fragments.
+const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// How long output queued on a non-blocking socket may wait for the
+/// client to take any of it before the connection is given up on.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much output may be queued for a client that isn't keeping up.
+const MAX_QUEUED_LENGTH: usize = 32 * 1024 * 1024;
+
+static NEXT_SOCKET_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Status codes sent in Close frames, from Section 7.4.1 of RFC 6455.
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+#[derive(PartialEq, Debug)]
+pub enum FrameError {
+    ProtocolError,
+    InvalidPayload,
+    MessageTooBig,
+    ConnectionClosed,
+}
+
+impl FrameError {
+    /// The status code to send to the client before dropping the
+    /// connection, or None if the connection is already gone.
+    pub fn close_code(&self) -> Option<u16> {
+        match *self {
+            FrameError::ProtocolError => Some(CLOSE_PROTOCOL_ERROR),
+            FrameError::InvalidPayload => Some(CLOSE_INVALID_PAYLOAD),
+            FrameError::MessageTooBig => Some(CLOSE_MESSAGE_TOO_BIG),
+            FrameError::ConnectionClosed => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_byte(byte: u8) -> Option<Opcode> {
+        match byte {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Control frames are the opcodes with the high bit set, as
+    /// described in Section 5.5 of RFC 6455.
+    pub fn is_control(self) -> bool {
+        self.to_byte() & 0x8 != 0
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Frame {
+    pub fin: bool,
+    pub rsv1: bool,
+    pub rsv2: bool,
+    pub rsv3: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Creates a single, unmasked frame as sent from a server to a
+    /// client.
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Frame {
+        Frame { fin: true, rsv1: false, rsv2: false, rsv3: false, opcode, mask: None, payload }
+    }
+
+    /// Serializes the frame using the wire format in Section 5.2 of
+    /// RFC 6455. The payload is masked if the frame has a masking
+    /// key.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.payload.len() + 14);
+
+        let mut first_byte = self.opcode.to_byte();
+        if self.fin { first_byte |= 0x80; }
+        if self.rsv1 { first_byte |= 0x40; }
+        if self.rsv2 { first_byte |= 0x20; }
+        if self.rsv3 { first_byte |= 0x10; }
+        output.push(first_byte);
+
+        let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+        let length = self.payload.len();
+
+        if length < 126 {
+            output.push(mask_bit | length as u8);
+        } else if length <= 0xFFFF {
+            output.push(mask_bit | 126);
+            output.extend_from_slice(&(length as u16).to_be_bytes());
+        } else {
+            output.push(mask_bit | 127);
+            output.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+
+        match self.mask {
+            Some(mask) => {
+                output.extend_from_slice(&mask);
+                let start = output.len();
+                output.extend_from_slice(&self.payload);
+                apply_mask(&mut output[start..], mask);
+            },
+            None => output.extend_from_slice(&self.payload),
+        }
+
+        output
+    }
+}
+
+/// Parses a single frame from the front of the buffer, returning the
+/// frame along with the number of bytes it occupied. Returns None if
+/// the buffer does not yet hold a complete frame. Masked payloads are
+/// unmasked before being returned.
+pub fn parse_frame(buffer: &[u8]) -> Result<Option<(Frame, usize)>, FrameError> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+
+    let opcode = match Opcode::from_byte(buffer[0] & 0x0F) {
+        Some(opcode) => opcode,
+        None => return Err(FrameError::ProtocolError),
+    };
+
+    let fin = buffer[0] & 0x80 != 0;
+    let masked = buffer[1] & 0x80 != 0;
+    let mut index = 2;
+
+    let length = match buffer[1] & 0x7F {
+        126 => {
+            if buffer.len() < 4 {
+                return Ok(None);
+            }
+            index = 4;
+            u16::from_be_bytes([buffer[2], buffer[3]]) as u64
+        },
+        127 => {
+            if buffer.len() < 10 {
+                return Ok(None);
+            }
+            index = 10;
+            let mut length_bytes = [0; 8];
+            length_bytes.copy_from_slice(&buffer[2..10]);
+            let length = u64::from_be_bytes(length_bytes);
+
+            // The most significant bit of a 64-bit length must be 0.
+            if length >> 63 != 0 {
+                return Err(FrameError::ProtocolError);
+            }
+            length
+        },
+        length => length as u64,
+    };
+
+    if opcode.is_control() && (!fin || length > 125) {
+        return Err(FrameError::ProtocolError);
+    }
+
+    if length > MAX_PAYLOAD_LENGTH {
+        return Err(FrameError::MessageTooBig);
+    }
+
+    let mask = if masked {
+        if buffer.len() < index + 4 {
+            return Ok(None);
+        }
+        let mut mask = [0; 4];
+        mask.copy_from_slice(&buffer[index..(index + 4)]);
+        index += 4;
+        Some(mask)
+    } else {
+        None
+    };
+
+    let length = length as usize;
+    if buffer.len() < index + length {
+        return Ok(None);
+    }
+
+    let mut payload = buffer[index..(index + length)].to_vec();
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+
+    let frame = Frame {
+        fin,
+        rsv1: buffer[0] & 0x40 != 0,
+        rsv2: buffer[0] & 0x20 != 0,
+        rsv3: buffer[0] & 0x10 != 0,
+        opcode,
+        mask,
+        payload,
+    };
+
+    Ok(Some((frame, index + length)))
+}
+
+/// XORs the data with the masking key as described in Section 5.3 of
+/// RFC 6455. Applying the same mask twice restores the original data.
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (index, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+}
+
+/// Returns true if the code may appear in a Close frame. Codes
+/// 1004-1006 and 1015 are reserved for reporting conditions locally
+/// and must never be sent over the wire, and 1016-2999 are reserved
+/// for future versions of the protocol.
+pub fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// Parses the body of a Close frame as described in Section 5.5.1 of
+/// RFC 6455. An empty body is allowed and yields None, otherwise the
+/// body must start with a valid status code followed by an optional
+/// UTF-8 reason.
+pub fn parse_close_payload(payload: &[u8]) -> Result<Option<(u16, &str)>, FrameError> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err(FrameError::ProtocolError),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            if !is_valid_close_code(code) {
+                return Err(FrameError::ProtocolError);
+            }
+
+            match str::from_utf8(&payload[2..]) {
+                Ok(reason) => Ok(Some((code, reason))),
+                Err(_) => Err(FrameError::InvalidPayload),
+            }
+        },
+    }
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(reason.len() + 2);
+    payload.extend_from_slice(&code.to_be_bytes());
+    payload.extend_from_slice(reason.as_bytes());
+    payload
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<(u16, String)>),
+}
+
+/// A message whose final fragment hasn't arrived yet.
+struct Fragments {
+    opcode: Opcode,
+    compressed: bool,
+    payload: Vec<u8>,
+}
+
+/// Joins fragmented data frames back into complete messages as
+/// described in Section 5.4 of RFC 6455. Control frames may arrive
+/// between the fragments of a message and are passed through as soon
+/// as they are seen. If permessage-deflate was negotiated, messages
+/// with the RSV1 bit set on their first frame are decompressed.
+pub struct MessageAssembler {
+    fragments: Option<Fragments>,
+    validator: Utf8Validator,
+    decompressor: Option<Decompressor>,
+}
+
+impl Default for MessageAssembler {
+    fn default() -> MessageAssembler {
+        MessageAssembler::new()
+    }
+}
+
+impl MessageAssembler {
+    pub fn new() -> MessageAssembler {
+        MessageAssembler { fragments: None, validator: Utf8Validator::new(), decompressor: None }
+    }
+
+    pub fn with_deflate(params: &DeflateParams) -> MessageAssembler {
+        MessageAssembler { decompressor: Some(Decompressor::new(params)), ..MessageAssembler::new() }
+    }
+
+    /// Adds a frame to the message in progress, returning the message
+    /// once its final frame arrives. Uncompressed text is validated as
+    /// each fragment comes in so that bad input fails fast rather than
+    /// after the whole message has been buffered.
+    pub fn push(&mut self, frame: Frame) -> Result<Option<Message>, FrameError> {
+        // Only the first frame of a compressed message may set RSV1.
+        if frame.rsv1 && (self.decompressor.is_none() || frame.opcode.is_control() || frame.opcode == Opcode::Continuation) {
+            return Err(FrameError::ProtocolError);
+        }
+
+        match frame.opcode {
+            Opcode::Ping => return Ok(Some(Message::Ping(frame.payload))),
+            Opcode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+            Opcode::Close => {
+                let close = parse_close_payload(&frame.payload)?
+                    .map(|(code, reason)| (code, reason.to_string()));
+                return Ok(Some(Message::Close(close)));
+            },
+            Opcode::Text | Opcode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(FrameError::ProtocolError);
+                }
+
+                self.validator = Utf8Validator::new();
+                self.fragments = Some(Fragments { opcode: frame.opcode, compressed: frame.rsv1, payload: Vec::new() });
+            },
+            Opcode::Continuation => {
+                if self.fragments.is_none() {
+                    return Err(FrameError::ProtocolError);
+                }
+            },
+        }
+
+        let mut fragments = self.fragments.take().unwrap();
+
+        if fragments.payload.len() + frame.payload.len() > MAX_MESSAGE_LENGTH {
+            return Err(FrameError::MessageTooBig);
+        }
+
+        if fragments.opcode == Opcode::Text && !fragments.compressed && !self.validator.feed(&frame.payload) {
+            return Err(FrameError::InvalidPayload);
+        }
+
+        fragments.payload.extend_from_slice(&frame.payload);
+
+        if !frame.fin {
+            self.fragments = Some(fragments);
+            return Ok(None);
+        }
+
+        let payload = match (fragments.compressed, self.decompressor.as_mut()) {
+            (true, Some(decompressor)) => decompressor.decompress(&fragments.payload, MAX_MESSAGE_LENGTH)?,
+            _ => fragments.payload,
+        };
+
+        match fragments.opcode {
+            Opcode::Text => {
+                if !fragments.compressed && !self.validator.is_complete() {
+                    return Err(FrameError::InvalidPayload);
+                }
+
+                // Uncompressed text has already been checked by the
+                // validator, so this only fails for compressed text.
+                match String::from_utf8(payload) {
+                    Ok(text) => Ok(Some(Message::Text(text))),
+                    Err(_) => Err(FrameError::InvalidPayload),
+                }
+            },
+            _ => Ok(Some(Message::Binary(payload))),
+        }
+    }
+}
+
+/// What was agreed on with the client during the opening handshake.
+#[derive(Default)]
+pub struct Handshake {
+    pub protocol: Option<&'static str>,
+    pub deflate: Option<DeflateParams>,
+    pub identity: Option<String>,
+    /// Bytes read past the end of the handshake request, which belong
+    /// to the first frames from the client.
+    pub buffered: Vec<u8>,
+}
+
+/// A handle for sending to a connected client. Handles are cheap to
+/// clone and may be used from any thread; writes are serialized so
+/// frames from different senders never interleave on the wire.
+#[derive(Clone)]
+pub struct Socket {
+    id: usize,
+    protocol: Option<&'static str>,
+    identity: Option<Arc<str>>,
+    writer: Arc<Mutex<Writer>>,
+}
+
+struct Writer {
+    stream: TcpStream,
+    closed: bool,
+    compressor: Option<Compressor>,
+    /// Set once the connection's stream is non-blocking.
+    queue: Option<WriteQueue>,
+}
+
+/// Called with true when output starts waiting for the client to make
+/// room, and with false once it has all gone out.
+pub type WriteInterest = Box<dyn Fn(bool) + Send>;
+
+/// Output a non-blocking stream couldn't take yet.
+struct WriteQueue {
+    pending: Vec<u8>,
+    /// When the client last took some of the output.
+    progress: Instant,
+    /// Shut the stream down once the pending output has gone out.
+    shutdown: bool,
+    interest: WriteInterest,
+    /// What `interest` was last told.
+    waiting: bool,
+}
+
+impl Socket {
+    fn new(stream: TcpStream, handshake: &Handshake) -> Socket {
+        let writer = Writer {
+            stream,
+            closed: false,
+            compressor: handshake.deflate.as_ref().map(Compressor::new),
+            queue: None,
+        };
+
+        Socket {
+            id: NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: handshake.protocol,
+            identity: handshake.identity.as_ref().map(|identity| Arc::from(identity.as_str())),
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    /// A number that identifies this connection for as long as the
+    /// server is running. Clones of a socket share its id.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The subprotocol agreed on during the opening handshake, if any.
+    pub fn protocol(&self) -> Option<&'static str> {
+        self.protocol
+    }
+
+    /// The identity the endpoint's `authorize` hook attached to the
+    /// connection, such as a user id.
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_ref().map(|identity| identity.as_ref())
+    }
+
+    /// Sends a Text or Binary message. Control messages are handled
+    /// by the connection itself and are ignored here.
+    pub fn send(&self, message: &Message) -> Result<(), FrameError> {
+        match *message {
+            Message::Text(ref text) => self.send_text(text),
+            Message::Binary(ref data) => self.send_binary(data),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn send_text(&self, text: &str) -> Result<(), FrameError> {
+        self.send_data(Opcode::Text, text.as_bytes())
+    }
+
+    pub fn send_binary(&self, data: &[u8]) -> Result<(), FrameError> {
+        self.send_data(Opcode::Binary, data)
+    }
+
+    /// Sends a data message, compressing it if permessage-deflate was
+    /// negotiated. The compressor's history has to match the order
+    /// messages go out in, so it is only used while holding the lock.
+    fn send_data(&self, opcode: Opcode, data: &[u8]) -> Result<(), FrameError> {
+        let mut writer = self.writer.lock().unwrap();
+
+        let frame = match writer.compressor {
+            Some(ref mut compressor) => {
+                let mut frame = Frame::new(opcode, compressor.compress(data));
+                frame.rsv1 = true;
+                frame
+            },
+            None => Frame::new(opcode, data.to_vec()),
+        };
+
+        writer.write_frame(&frame)
+    }
+
+    /// Writes a frame to the client as-is. Nothing more may be sent
+    /// once a Close frame has gone out.
+    pub fn send_frame(&self, frame: &Frame) -> Result<(), FrameError> {
+        self.writer.lock().unwrap().write_frame(frame)
+    }
+
+    /// Starts the closing handshake by sending a Close frame. The
+    /// connection stays open for reading until the client replies
+    /// with its own Close.
+    pub fn close(&self, code: Option<u16>, reason: &str) {
+        let payload = match code {
+            Some(code) => close_payload(code, reason),
+            None => Vec::new(),
+        };
+
+        let _ = self.send_frame(&Frame::new(Opcode::Close, payload));
+    }
+
+    /// Whether nothing more can be sent, because a Close frame has gone
+    /// out or the socket has been shut down.
+    fn is_closed(&self) -> bool {
+        self.writer.lock().unwrap().closed
+    }
+
+    /// Shuts the socket down straight away, discarding any output still
+    /// queued for the client.
+    pub(crate) fn shutdown(&self) {
+        self.writer.lock().unwrap().shutdown();
+    }
+}
+
+impl Writer {
+    fn write_frame(&mut self, frame: &Frame) -> Result<(), FrameError> {
+        if self.closed {
+            return Err(FrameError::ConnectionClosed);
+        }
+
+        if frame.opcode == Opcode::Close {
+            self.closed = true;
+        }
+
+        let bytes = frame.encode();
+
+        let queue = match self.queue {
+            Some(ref mut queue) => queue,
+            None => return self.stream.write_all(&bytes).map_err(|_| FrameError::ConnectionClosed),
+        };
+
+        if queue.pending.len() + bytes.len() > MAX_QUEUED_LENGTH {
+            self.shutdown();
+            return Err(FrameError::ConnectionClosed);
+        }
+
+        if queue.pending.is_empty() {
+            queue.progress = Instant::now();
+        }
+
+        queue.pending.extend_from_slice(&bytes);
+        self.flush()
+    }
+
+    /// Writes as much of the queued output as the stream will take
+    /// without blocking.
+    fn flush(&mut self) -> Result<(), FrameError> {
+        let queue = match self.queue {
+            Some(ref mut queue) => queue,
+            None => return Ok(()),
+        };
+
+        let mut written = 0;
+
+        while written < queue.pending.len() {
+            match self.stream.write(&queue.pending[written..]) {
+                Ok(count) if count > 0 => written += count,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                _ => {
+                    self.shutdown();
+                    return Err(FrameError::ConnectionClosed);
+                },
+            }
+        }
+
+        if written > 0 {
+            queue.pending.drain(..written);
+            queue.progress = Instant::now();
+        }
+
+        let waiting = !queue.pending.is_empty();
+        if waiting != queue.waiting {
+            queue.waiting = waiting;
+            (queue.interest)(waiting);
+        }
+
+        if !waiting && queue.shutdown {
+            self.shutdown();
+        }
+
+        Ok(())
+    }
+
+    /// Shuts the stream down once the queued output has gone out.
+    fn finish(&mut self) {
+        match self.queue {
+            Some(ref mut queue) if !queue.pending.is_empty() => queue.shutdown = true,
+            _ => self.shutdown(),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.closed = true;
+        self.queue = None;
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    fn is_stalled(&self) -> bool {
+        match self.queue {
+            Some(ref queue) => !queue.pending.is_empty() && queue.progress.elapsed() >= WRITE_TIMEOUT,
+            None => false,
+        }
+    }
+}
+
+pub struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    assembler: MessageAssembler,
+    socket: Socket,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, handshake: Handshake) -> Result<Connection, FrameError> {
+        let writer = stream.try_clone().map_err(|_| FrameError::ConnectionClosed)?;
+
+        let assembler = match handshake.deflate {
+            Some(ref params) => MessageAssembler::with_deflate(params),
+            None => MessageAssembler::new(),
+        };
+
+        let socket = Socket::new(writer, &handshake);
+
+        Ok(Connection { stream, buffer: handshake.buffered, assembler, socket })
+    }
+
+    pub fn socket(&self) -> Socket {
+        self.socket.clone()
+    }
+
+    /// For connections whose stream is non-blocking, as in an event
+    /// loop. Sends then queue whatever the client hasn't made room for
+    /// rather than waiting, and `interest` is told when there is output
+    /// waiting, so the loop can watch for the stream becoming writable
+    /// and call `flush`.
+    pub fn queue_writes(&mut self, interest: WriteInterest) {
+        let mut writer = self.socket.writer.lock().unwrap();
+
+        writer.queue = Some(WriteQueue {
+            pending: Vec::new(),
+            progress: Instant::now(),
+            shutdown: false,
+            interest,
+            waiting: false,
+        });
+    }
+
+    /// Writes as much queued output as the stream will take.
+    pub fn flush(&mut self) -> Result<(), FrameError> {
+        self.socket.writer.lock().unwrap().flush()
+    }
+
+    /// Whether there is queued output still to go out.
+    pub fn has_queued_output(&self) -> bool {
+        match self.socket.writer.lock().unwrap().queue {
+            Some(ref queue) => !queue.pending.is_empty(),
+            None => false,
+        }
+    }
+
+    /// Whether queued output has waited `WRITE_TIMEOUT` for the client
+    /// to take any of it.
+    pub fn is_stalled(&self) -> bool {
+        self.socket.writer.lock().unwrap().is_stalled()
+    }
+
+    /// Blocks until a complete message or control frame has been read
+    /// from the stream.
+    pub fn read_message(&mut self) -> Result<Message, FrameError> {
+        loop {
+            if let Some(message) = self.next_message()? {
+                return Ok(message);
+            }
+
+            self.read_more()?;
+        }
+    }
+
+    /// Blocks until a complete frame has been read from the stream.
+    pub fn read_frame(&mut self) -> Result<Frame, FrameError> {
+        loop {
+            if let Some(frame) = self.next_frame()? {
+                return Ok(frame);
+            }
+
+            self.read_more()?;
+        }
+    }
+
+    /// Returns the next message or control frame if enough of it has
+    /// already been read, without touching the stream.
+    pub fn next_message(&mut self) -> Result<Option<Message>, FrameError> {
+        while let Some(frame) = self.next_frame()? {
+            if let Some(message) = self.assembler.push(frame)? {
+                return Ok(Some(message));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>, FrameError> {
+        match parse_frame(&self.buffer)? {
+            Some((frame, length)) => {
+                self.buffer.drain(..length);
+
+                // Section 5.1 requires every frame from a client to be
+                // masked. RSV1 is checked by the assembler since it
+                // depends on permessage-deflate, but no extension we
+                // support gives meaning to the other reserved bits.
+                if frame.mask.is_none() || frame.rsv2 || frame.rsv3 {
+                    return Err(FrameError::ProtocolError);
+                }
+
+                Ok(Some(frame))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Reads whatever the stream has for us into the buffer. On a
+    /// non-blocking stream with nothing to read, this returns
+    /// immediately with the buffer unchanged.
+    pub fn read_more(&mut self) -> Result<(), FrameError> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(FrameError::ConnectionClosed),
+            Ok(count) => {
+                self.buffer.extend_from_slice(&chunk[..count]);
+                Ok(())
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::Interrupted => Ok(()),
+            Err(_) => Err(FrameError::ConnectionClosed),
+        }
+    }
+
+    /// Sends a Close frame, unless one was already sent, and shuts
+    /// down the socket once any queued output has gone out. Per
+    /// Section 7.1.1 of RFC 6455 the server is the side that closes
+    /// the underlying TCP connection.
+    pub fn close(&mut self, code: Option<u16>, reason: &str) {
+        self.socket.close(code, reason);
+        self.socket.writer.lock().unwrap().finish();
+    }
+
+    /// Closes the connection with the status code matching the error.
+    pub fn fail(&mut self, error: &FrameError) {
+        match error.close_code() {
+            Some(code) => self.close(Some(code), ""),
+            None => self.socket.shutdown(),
+        }
+    }
+}
+
+/// Callbacks for a WebSocket endpoint. Each connection to the endpoint
+/// gets its own `Socket`, which the handler may keep to send messages
+/// later on.
+pub trait Handler: Send + Sync {
+    /// The subprotocols the endpoint speaks, such as `graphql-ws`.
+    /// The first one the client offers that appears here is chosen.
+    fn protocols(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// If true, clients that don't offer any of the endpoint's
+    /// subprotocols are turned away during the handshake.
+    fn requires_protocol(&self) -> bool {
+        false
+    }
+
+    /// Checks the Origin header of the upgrade request. Browsers
+    /// always send one, so this stops other sites' pages from opening
+    /// sockets with our users' cookies. Mismatches are refused with a
+    /// 403.
+    fn allows_origin(&self, _origin: Option<&str>) -> bool {
+        true
+    }
+
+    /// Called with the upgrade request before the 101 is sent, so the
+    /// endpoint can check cookies, query parameters or the
+    /// Authorization header. An error refuses the upgrade with that
+    /// status, and an identity returned here is attached to the
+    /// connection's `Socket`.
+    fn authorize(&self, _request: &Request) -> Result<Option<String>, HTTPError> {
+        Ok(None)
+    }
+
+    fn on_open(&self, _socket: &Socket) {}
+
+    /// Called with each complete Text or Binary message. Control
+    /// frames are handled by the server and never reach the handler.
+    fn on_message(&self, socket: &Socket, message: Message);
+
+    /// Called once when the connection ends. The code is the one the
+    /// connection was closed with, or None if it was dropped without
+    /// a status.
+    fn on_close(&self, _socket: &Socket, _code: Option<u16>, _reason: &str) {}
+
+    fn on_error(&self, _socket: &Socket, _error: &FrameError) {}
+}
+
+/// Returns true if the origin is on the allow-list. Origins are
+/// compared without regard to case, since browsers serialize the
+/// scheme and host in lowercase but people don't always type them
+/// that way. Requests without an Origin header don't come from a
+/// browser and are let through, as they can't carry a victim's
+/// cookies from another site.
+pub fn origin_allowed(origin: Option<&str>, allowed: &[String]) -> bool {
+    match origin {
+        Some(origin) => allowed.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin.trim())),
+        None => true,
+    }
+}
+
+/// Picks the subprotocol for a connection from the comma-separated
+/// list in the client's Sec-WebSocket-Protocol header. The client lists
+/// its protocols in order of preference, so the first one we support
+/// wins. Protocol names are case-sensitive.
+pub fn select_protocol(offered: &str, supported: &[&'static str]) -> Option<&'static str> {
+    offered.split(',')
+        .map(|protocol| protocol.trim())
+        .filter_map(|protocol| supported.iter().find(|supported| **supported == protocol))
+        .cloned()
+        .next()
+}
+
+/// Reads messages from an upgraded connection and passes them to the
+/// handler until the client closes it or sends something we can't
+/// understand. Pings are answered immediately and a Close from the
+/// client is echoed back to complete the closing handshake. The
+/// connection is a member of the hub for as long as it is open.
+pub fn serve(mut connection: Connection, handler: &dyn Handler, hub: &Hub) {
+    let socket = connection.socket();
+    hub.add(&socket);
+    handler.on_open(&socket);
+
+    let (code, reason) = loop {
+        let result = connection.read_message();
+
+        if let Some(end) = dispatch(&mut connection, handler, result) {
+            break end;
+        }
+    };
+
+    hub.remove(&socket);
+    handler.on_close(&socket, code, &reason);
+}
+
+/// Deals with one message or error read from the connection, as
+/// described for `serve`. Returns the close code and reason once the
+/// connection is over.
+pub fn dispatch(connection: &mut Connection, handler: &dyn Handler, result: Result<Message, FrameError>) -> Option<(Option<u16>, String)> {
+    let socket = connection.socket();
+
+    match result {
+        Ok(Message::Ping(payload)) => match socket.send_frame(&Frame::new(Opcode::Pong, payload)) {
+            Ok(()) => {},
+            // Section 5.5.1 lets the Pong go unsent once our Close has
+            // gone out. The client's Close is still to be read.
+            Err(FrameError::ConnectionClosed) if socket.is_closed() => {},
+            Err(error) => {
+                handler.on_error(&socket, &error);
+                connection.fail(&error);
+                return Some((None, String::new()));
+            },
+        },
+        Ok(Message::Pong(_)) => {},
+        Ok(Message::Close(Some((code, reason)))) => {
+            connection.close(Some(code), &reason);
+            return Some((Some(code), reason));
+        },
+        Ok(Message::Close(None)) => {
+            connection.close(None, "");
+            return Some((None, String::new()));
+        },
+        Ok(message) => handler.on_message(&socket, message),
+        Err(error) => {
+            handler.on_error(&socket, &error);
+            connection.fail(&error);
+            return Some((error.close_code(), String::new()));
+        },
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn parse_frame_reads_unmasked_text_frame_from_rfc6455() {
+        let input = [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+        let (frame, length) = parse_frame(&input).unwrap().unwrap();
+
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, Opcode::Text);
+        assert_eq!(frame.mask, None);
+        assert_eq!(frame.payload, b"Hello");
+        assert_eq!(length, 7);
+    }
+
+    #[test]
+    fn parse_frame_unmasks_masked_text_frame_from_rfc6455() {
+        let input = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, length) = parse_frame(&input).unwrap().unwrap();
+
+        assert_eq!(frame.mask, Some([0x37, 0xfa, 0x21, 0x3d]));
+        assert_eq!(frame.payload, b"Hello");
+        assert_eq!(length, 11);
+    }
+
+    #[test]
+    fn parse_frame_reads_fragmented_frames_from_rfc6455() {
+        let input = [0x01, 0x03, 0x48, 0x65, 0x6c, 0x80, 0x02, 0x6c, 0x6f];
+
+        let (first, length) = parse_frame(&input).unwrap().unwrap();
+        let (second, _) = parse_frame(&input[length..]).unwrap().unwrap();
+
+        assert!(!first.fin);
+        assert_eq!(first.opcode, Opcode::Text);
+        assert_eq!(first.payload, b"Hel");
+        assert!(second.fin);
+        assert_eq!(second.opcode, Opcode::Continuation);
+        assert_eq!(second.payload, b"lo");
+    }
+
+    #[test]
+    fn parse_frame_reads_16_bit_payload_length() {
+        let mut input = vec![0x82, 0x7E, 0x01, 0x00];
+        input.extend_from_slice(&[0xAB; 256]);
+
+        let (frame, length) = parse_frame(&input).unwrap().unwrap();
+
+        assert_eq!(frame.opcode, Opcode::Binary);
+        assert_eq!(frame.payload.len(), 256);
+        assert_eq!(length, 260);
+    }
+
+    #[test]
+    fn parse_frame_reads_64_bit_payload_length() {
+        let mut input = vec![0x82, 0x7F, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00];
+        input.extend_from_slice(&[0xAB; 65536]);
+
+        let (frame, length) = parse_frame(&input).unwrap().unwrap();
+
+        assert_eq!(frame.payload.len(), 65536);
+        assert_eq!(length, 65546);
+    }
+
+    #[test]
+    fn parse_frame_returns_none_until_frame_is_complete() {
+        let input = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+
+        for end in 0..input.len() {
+            assert_eq!(parse_frame(&input[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn parse_frame_rejects_reserved_opcodes() {
+        let input = [0x83, 0x00];
+        assert_eq!(parse_frame(&input), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn parse_frame_rejects_fragmented_control_frames() {
+        let input = [0x09, 0x00];
+        assert_eq!(parse_frame(&input), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn parse_frame_rejects_control_frames_longer_than_125_bytes() {
+        let input = [0x89, 0x7E, 0x00, 0x7E];
+        assert_eq!(parse_frame(&input), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn parse_frame_rejects_payloads_over_the_limit() {
+        let input = [0x82, 0x7F, 0, 0, 0, 0x01, 0, 0, 0, 0];
+        assert_eq!(parse_frame(&input), Err(FrameError::MessageTooBig));
+    }
+
+    #[test]
+    fn is_valid_close_code_accepts_registered_and_private_codes() {
+        for code in &[1000, 1001, 1002, 1003, 1007, 1011, 1014, 3000, 4999] {
+            assert!(is_valid_close_code(*code), "{} should be valid", code);
+        }
+    }
+
+    #[test]
+    fn is_valid_close_code_rejects_reserved_codes() {
+        for code in &[0, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000, 65535] {
+            assert!(!is_valid_close_code(*code), "{} should be invalid", code);
+        }
+    }
+
+    #[test]
+    fn parse_close_payload_allows_empty_body() {
+        assert_eq!(parse_close_payload(b""), Ok(None));
+    }
+
+    #[test]
+    fn parse_close_payload_reads_code_and_reason() {
+        let payload = close_payload(1001, "bye");
+        assert_eq!(parse_close_payload(&payload), Ok(Some((1001, "bye"))));
+    }
+
+    #[test]
+    fn parse_close_payload_rejects_single_byte_body() {
+        assert_eq!(parse_close_payload(&[0x03]), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn parse_close_payload_rejects_invalid_codes() {
+        let payload = close_payload(1005, "");
+        assert_eq!(parse_close_payload(&payload), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn parse_close_payload_rejects_reason_that_isnt_utf8() {
+        let payload = [0x03, 0xE8, 0xCE, 0xBA, 0xFF];
+        assert_eq!(parse_close_payload(&payload), Err(FrameError::InvalidPayload));
+    }
+
+    fn fragment(opcode: Opcode, fin: bool, payload: &[u8]) -> Frame {
+        let mut frame = Frame::new(opcode, payload.to_vec());
+        frame.fin = fin;
+        frame
+    }
+
+    #[test]
+    fn assembler_returns_unfragmented_messages_immediately() {
+        let mut assembler = MessageAssembler::new();
+
+        let text = assembler.push(fragment(Opcode::Text, true, b"Hello")).unwrap();
+        let binary = assembler.push(fragment(Opcode::Binary, true, &[1, 2])).unwrap();
+
+        assert_eq!(text, Some(Message::Text("Hello".to_string())));
+        assert_eq!(binary, Some(Message::Binary(vec![1, 2])));
+    }
+
+    #[test]
+    fn assembler_joins_continuation_frames() {
+        let mut assembler = MessageAssembler::new();
+
+        assert_eq!(assembler.push(fragment(Opcode::Text, false, b"Hel")), Ok(None));
+        assert_eq!(assembler.push(fragment(Opcode::Continuation, false, b"l")), Ok(None));
+        let message = assembler.push(fragment(Opcode::Continuation, true, b"o")).unwrap();
+
+        assert_eq!(message, Some(Message::Text("Hello".to_string())));
+    }
+
+    #[test]
+    fn assembler_passes_control_frames_through_mid_message() {
+        let mut assembler = MessageAssembler::new();
+
+        assert_eq!(assembler.push(fragment(Opcode::Binary, false, &[1])), Ok(None));
+        let ping = assembler.push(fragment(Opcode::Ping, true, b"ping")).unwrap();
+        let message = assembler.push(fragment(Opcode::Continuation, true, &[2])).unwrap();
+
+        assert_eq!(ping, Some(Message::Ping(b"ping".to_vec())));
+        assert_eq!(message, Some(Message::Binary(vec![1, 2])));
+    }
+
+    #[test]
+    fn assembler_rejects_continuation_without_a_message_in_progress() {
+        let mut assembler = MessageAssembler::new();
+        let result = assembler.push(fragment(Opcode::Continuation, true, b"lo"));
+
+        assert_eq!(result, Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn assembler_rejects_new_message_before_previous_one_finishes() {
+        let mut assembler = MessageAssembler::new();
+        assembler.push(fragment(Opcode::Text, false, b"Hel")).unwrap();
+        let result = assembler.push(fragment(Opcode::Text, true, b"lo"));
+
+        assert_eq!(result, Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn assembler_accepts_character_split_between_fragments() {
+        let mut assembler = MessageAssembler::new();
+
+        assert_eq!(assembler.push(fragment(Opcode::Text, false, &[0xCE])), Ok(None));
+        let message = assembler.push(fragment(Opcode::Continuation, true, &[0xBA])).unwrap();
+
+        assert_eq!(message, Some(Message::Text("κ".to_string())));
+    }
+
+    #[test]
+    fn assembler_rejects_invalid_utf8_in_first_fragment() {
+        let mut assembler = MessageAssembler::new();
+        let result = assembler.push(fragment(Opcode::Text, false, &[0x48, 0xFF]));
+
+        assert_eq!(result, Err(FrameError::InvalidPayload));
+    }
+
+    #[test]
+    fn assembler_rejects_text_ending_mid_character() {
+        let mut assembler = MessageAssembler::new();
+        assembler.push(fragment(Opcode::Text, false, b"a")).unwrap();
+        let result = assembler.push(fragment(Opcode::Continuation, true, &[0xE2, 0x82]));
+
+        assert_eq!(result, Err(FrameError::InvalidPayload));
+    }
+
+    #[test]
+    fn assembler_does_not_validate_binary_messages() {
+        let mut assembler = MessageAssembler::new();
+        let message = assembler.push(fragment(Opcode::Binary, true, &[0xFF])).unwrap();
+
+        assert_eq!(message, Some(Message::Binary(vec![0xFF])));
+    }
+
+    fn deflate_params() -> DeflateParams {
+        DeflateParams::negotiate("permessage-deflate").unwrap()
+    }
+
+    #[test]
+    fn assembler_rejects_rsv1_without_deflate() {
+        let mut assembler = MessageAssembler::new();
+        let mut frame = fragment(Opcode::Text, true, b"Hello");
+        frame.rsv1 = true;
+
+        assert_eq!(assembler.push(frame), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn assembler_decompresses_example_from_rfc7692() {
+        let mut assembler = MessageAssembler::with_deflate(&deflate_params());
+        let mut frame = fragment(Opcode::Text, true, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        frame.rsv1 = true;
+
+        assert_eq!(assembler.push(frame), Ok(Some(Message::Text("Hello".to_string()))));
+    }
+
+    #[test]
+    fn assembler_decompresses_fragmented_messages() {
+        let mut assembler = MessageAssembler::with_deflate(&deflate_params());
+        let mut first = fragment(Opcode::Text, false, &[0xf2, 0x48, 0xcd]);
+        first.rsv1 = true;
+
+        assert_eq!(assembler.push(first), Ok(None));
+        let message = assembler.push(fragment(Opcode::Continuation, true, &[0xc9, 0xc9, 0x07, 0x00]));
+
+        assert_eq!(message, Ok(Some(Message::Text("Hello".to_string()))));
+    }
+
+    #[test]
+    fn assembler_accepts_uncompressed_messages_with_deflate() {
+        let mut assembler = MessageAssembler::with_deflate(&deflate_params());
+        let message = assembler.push(fragment(Opcode::Binary, true, &[1, 2]));
+
+        assert_eq!(message, Ok(Some(Message::Binary(vec![1, 2]))));
+    }
+
+    #[test]
+    fn assembler_rejects_rsv1_on_continuation_and_control_frames() {
+        let mut assembler = MessageAssembler::with_deflate(&deflate_params());
+        assembler.push(fragment(Opcode::Text, false, b"a")).unwrap();
+        let mut continuation = fragment(Opcode::Continuation, true, b"b");
+        continuation.rsv1 = true;
+        let mut ping = fragment(Opcode::Ping, true, b"");
+        ping.rsv1 = true;
+
+        assert_eq!(assembler.push(continuation), Err(FrameError::ProtocolError));
+        assert_eq!(assembler.push(ping), Err(FrameError::ProtocolError));
+    }
+
+    #[test]
+    fn assembler_rejects_compressed_text_that_isnt_utf8() {
+        let mut compressor = Compressor::new(&deflate_params());
+        let mut assembler = MessageAssembler::with_deflate(&deflate_params());
+        let mut frame = fragment(Opcode::Text, true, &compressor.compress(&[0xFF, 0xFE]));
+        frame.rsv1 = true;
+
+        assert_eq!(assembler.push(frame), Err(FrameError::InvalidPayload));
+    }
+
+    #[test]
+    fn origin_allowed_matches_allow_list_ignoring_case() {
+        let allowed = vec!["https://example.com".to_string(), "http://localhost:4485".to_string()];
+
+        assert!(origin_allowed(Some("https://example.com"), &allowed));
+        assert!(origin_allowed(Some("HTTPS://Example.com"), &allowed));
+        assert!(origin_allowed(Some("http://localhost:4485"), &allowed));
+        assert!(!origin_allowed(Some("https://evil.example"), &allowed));
+        assert!(!origin_allowed(Some("https://example.com:8443"), &allowed));
+        assert!(!origin_allowed(Some("null"), &allowed));
+    }
+
+    #[test]
+    fn origin_allowed_lets_requests_without_origin_through() {
+        assert!(origin_allowed(None, &["https://example.com".to_string()]));
+    }
+
+    #[test]
+    fn select_protocol_picks_first_offered_protocol_that_is_supported() {
+        let supported = ["json.v1", "graphql-ws"];
+
+        assert_eq!(select_protocol("mqtt, graphql-ws, json.v1", &supported), Some("graphql-ws"));
+        assert_eq!(select_protocol("json.v1", &supported), Some("json.v1"));
+    }
+
+    #[test]
+    fn select_protocol_returns_none_without_a_match() {
+        assert_eq!(select_protocol("mqtt", &["json.v1"]), None);
+        assert_eq!(select_protocol("JSON.V1", &["json.v1"]), None);
+        assert_eq!(select_protocol("", &["json.v1"]), None);
+        assert_eq!(select_protocol("json.v1", &[]), None);
+    }
+
+    #[test]
+    fn encode_writes_unmasked_frames() {
+        let frame = Frame::new(Opcode::Text, b"Hello".to_vec());
+        assert_eq!(frame.encode(), vec![0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+    }
+
+    #[test]
+    fn encode_uses_extended_payload_lengths() {
+        let medium = Frame::new(Opcode::Binary, vec![0; 256]).encode();
+        let large = Frame::new(Opcode::Binary, vec![0; 65536]).encode();
+
+        assert_eq!(&medium[..4], &[0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(&large[..10], &[0x82, 0x7F, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn encode_and_parse_frame_round_trip_masked_frames() {
+        let mut frame = Frame::new(Opcode::Binary, vec![1, 2, 3, 4, 5]);
+        frame.mask = Some([0xDE, 0xAD, 0xBE, 0xEF]);
+
+        let encoded = frame.encode();
+        let (parsed, length) = parse_frame(&encoded).unwrap().unwrap();
+
+        assert_eq!(parsed, frame);
+        assert_eq!(length, encoded.len());
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        errors: Mutex<Vec<String>>,
+        closes: Mutex<Vec<(Option<u16>, String)>>,
+    }
+
+    impl Handler for Recorder {
+        fn on_message(&self, _socket: &Socket, _message: Message) {}
+
+        fn on_close(&self, _socket: &Socket, code: Option<u16>, reason: &str) {
+            self.closes.lock().unwrap().push((code, reason.to_string()));
+        }
+
+        fn on_error(&self, _socket: &Socket, error: &FrameError) {
+            self.errors.lock().unwrap().push(format!("{:?}", error));
+        }
+    }
+
+    fn masked(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Frame::new(opcode, payload.to_vec());
+        frame.mask = Some([0xDE, 0xAD, 0xBE, 0xEF]);
+        frame.encode()
+    }
+
+    #[test]
+    fn serve_skips_pongs_after_closing_and_finishes_the_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let connection = Connection::new(server, Handshake::default()).unwrap();
+
+        connection.socket().close(Some(CLOSE_GOING_AWAY), "bye");
+        client.write_all(&masked(Opcode::Ping, b"still there?")).unwrap();
+        client.write_all(&masked(Opcode::Close, &close_payload(1000, ""))).unwrap();
+
+        let handler = Recorder::default();
+        serve(connection, &handler, &Hub::new());
+
+        assert!(handler.errors.lock().unwrap().is_empty());
+        assert_eq!(*handler.closes.lock().unwrap(), vec![(Some(1000), String::new())]);
+
+        // Only our Close went out, and then the connection was closed.
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        let (close, length) = parse_frame(&received).unwrap().unwrap();
+
+        assert_eq!(parse_close_payload(&close.payload), Ok(Some((CLOSE_GOING_AWAY, "bye"))));
+        assert_eq!(length, received.len());
+    }
+
+    /// A non-blocking connection over loopback with its writes queued,
+    /// along with the client's end and what the connection has said
+    /// about output waiting.
+    fn queued_connection() -> (Connection, TcpStream, Arc<Mutex<Vec<bool>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+
+        let interest = Arc::new(Mutex::new(Vec::new()));
+        let record = interest.clone();
+
+        let mut connection = Connection::new(server, Handshake::default()).unwrap();
+        connection.queue_writes(Box::new(move |waiting| record.lock().unwrap().push(waiting)));
+
+        (connection, client, interest)
+    }
+
+    #[test]
+    fn queued_connection_sends_without_waiting_for_the_client() {
+        let (mut connection, mut client, interest) = queued_connection();
+
+        // Far more than the socket buffers hold, so a blocking write
+        // would wait for the client to read.
+        let data = vec![7; 8 * 1024 * 1024];
+        connection.socket().send_binary(&data).unwrap();
+        connection.close(Some(1000), "done");
+
+        assert!(connection.has_queued_output());
+        assert_eq!(*interest.lock().unwrap(), vec![true]);
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        while connection.has_queued_output() {
+            connection.flush().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(*interest.lock().unwrap(), vec![true, false]);
+
+        // The connection is only shut down once the Close frame is out.
+        let received = reader.join().unwrap();
+        let (binary, length) = parse_frame(&received).unwrap().unwrap();
+        let (close, _) = parse_frame(&received[length..]).unwrap().unwrap();
+
+        assert_eq!(binary.payload, data);
+        assert_eq!(parse_close_payload(&close.payload), Ok(Some((1000, "done"))));
+    }
+
+    #[test]
+    fn queued_connection_is_closed_once_too_much_is_waiting() {
+        let (connection, _client, _) = queued_connection();
+        let socket = connection.socket();
+
+        assert_eq!(socket.send_binary(&vec![0; MAX_QUEUED_LENGTH]), Err(FrameError::ConnectionClosed));
+        assert!(!connection.has_queued_output());
+        assert_eq!(socket.send_text("hello"), Err(FrameError::ConnectionClosed));
+    }
+}